@@ -0,0 +1,401 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::Path;
+
+use deno_ast::swc::ast::CallExpr;
+use deno_ast::swc::ast::Callee;
+use deno_ast::swc::ast::Expr;
+use deno_ast::swc::ast::Lit;
+use deno_ast::swc::ast::Program;
+use deno_ast::swc::visit::Visit;
+use deno_ast::swc::visit::VisitWith;
+use deno_ast::Diagnostic;
+use deno_ast::MediaType;
+use deno_ast::ParseParams;
+use deno_ast::ParsedSource;
+use deno_ast::SourceTextInfo;
+use deno_core::ModuleSpecifier;
+
+use crate::package_json;
+
+/// Whether a JavaScript module is CommonJS: `.cjs`/`.cts` files, `.js`
+/// files in a package whose package.json sets `"type": "commonjs"`, and
+/// otherwise scripts without `import`/`export` that use `require` or
+/// `exports`.
+pub fn is_cjs(specifier: &ModuleSpecifier, media_type: MediaType, code: &str) -> bool {
+    match media_type {
+        MediaType::Cjs | MediaType::Cts => true,
+        MediaType::JavaScript => {
+            let package_type = specifier.to_file_path().ok().and_then(|path| {
+                let package_dir = path
+                    .ancestors()
+                    .skip(1)
+                    .find(|dir| dir.join("package.json").is_file())?;
+                let package_json = package_json::read(package_dir).ok()?;
+                Some(package_json["type"].as_str()?.to_string())
+            });
+            match package_type.as_deref() {
+                Some("commonjs") => true,
+                Some("module") => false,
+                _ => looks_like_cjs(specifier, code),
+            }
+        }
+        _ => false,
+    }
+}
+
+fn looks_like_cjs(specifier: &ModuleSpecifier, code: &str) -> bool {
+    if !code.contains("require") && !code.contains("exports") {
+        return false;
+    }
+    // Parsing only yields a script when there are no module declarations.
+    parse(specifier, code).is_ok_and(|parsed| matches!(parsed.program_ref(), Program::Script(_)))
+}
+
+fn parse(specifier: &ModuleSpecifier, code: &str) -> Result<ParsedSource, Diagnostic> {
+    deno_ast::parse_program(ParseParams {
+        specifier: specifier.to_string(),
+        text_info: SourceTextInfo::from_string(code.to_string()),
+        media_type: MediaType::JavaScript,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })
+}
+
+/// Wraps a CommonJS module in an ES module exposing `module.exports` as its
+/// default export and as a `"module.exports"` export, plus the named exports
+/// that can be detected statically.
+///
+/// `require` calls with a string literal are turned into static imports of
+/// the same specifier, so they load through the same loader. Other
+/// `require` calls, and those of modules that do not exist or that
+/// `can_resolve` rejects, throw a `MODULE_NOT_FOUND` error when they run, so
+/// that optional dependencies can be caught. The wrapper's prologue is kept
+/// on the first line so that line numbers of the wrapped code are unchanged.
+pub fn wrap(code: &str, specifier: &ModuleSpecifier, can_resolve: impl Fn(&str) -> bool) -> String {
+    let path = specifier.to_file_path().ok();
+    let filename = match &path {
+        Some(path) => path.display().to_string(),
+        None => specifier.to_string(),
+    };
+    let dirname = match path.as_deref().and_then(Path::parent) {
+        Some(dir) => dir.display().to_string(),
+        None => specifier
+            .join(".")
+            .map(|s| s.to_string())
+            .unwrap_or_default(),
+    };
+
+    let mut prologue = String::new();
+    let mut deps = String::new();
+    for (i, required) in required_specifiers(specifier, code).iter().enumerate() {
+        if required.starts_with("node:") {
+            continue;
+        }
+        let import_specifier = if is_relative(required) {
+            probe_relative(required, path.as_deref())
+        } else {
+            Some(required.clone())
+        };
+        let Some(import_specifier) = import_specifier.filter(|s| can_resolve(s)) else {
+            continue;
+        };
+        if import_specifier.ends_with(".json") {
+            write!(
+                prologue,
+                "import __cjs_dep{i} from {} assert {{ type: \"json\" }};",
+                js_string(&import_specifier)
+            )
+            .unwrap();
+            write!(
+                deps,
+                "{}: {{ \"module.exports\": __cjs_dep{i} }},",
+                js_string(required)
+            )
+            .unwrap();
+        } else {
+            write!(
+                prologue,
+                "import * as __cjs_dep{i} from {};",
+                js_string(&import_specifier)
+            )
+            .unwrap();
+            write!(deps, "{}: __cjs_dep{i},", js_string(required)).unwrap();
+        }
+    }
+    write!(
+        prologue,
+        "const __cjs_deps = {{{deps}}};\
+         function __cjs_require(specifier) {{\
+         if (!Object.prototype.hasOwnProperty.call(__cjs_deps, specifier)) {{\
+         const error = new Error(`Cannot find module \"${{specifier}}\"; only requires of modules that resolve, with a string literal, are supported`);\
+         error.code = \"MODULE_NOT_FOUND\";\
+         throw error;\
+         }}\
+         const ns = __cjs_deps[specifier];\
+         return \"module.exports\" in ns ? ns[\"module.exports\"] : ns;\
+         }}\
+         const __cjs_module = {{ exports: {{}} }};\
+         (function (exports, require, module, __filename, __dirname) {{"
+    )
+    .unwrap();
+
+    let mut epilogue = format!(
+        "\n}}).call(__cjs_module.exports, __cjs_module.exports, __cjs_require, __cjs_module, {}, {});\n\
+         const __cjs_exports = __cjs_module.exports;\n\
+         export default __cjs_exports;\n\
+         export {{ __cjs_exports as \"module.exports\" }};\n",
+        js_string(&filename),
+        js_string(&dirname)
+    );
+    for (i, name) in named_exports(code).iter().enumerate() {
+        writeln!(
+            epilogue,
+            "const __cjs_export{i} = __cjs_exports[{0}];\nexport {{ __cjs_export{i} as {0} }};",
+            js_string(name)
+        )
+        .unwrap();
+    }
+
+    // A hashbang is only valid at the very start of a file, which is now the
+    // prologue, so it is turned into a comment of the same length.
+    match code.strip_prefix("#!") {
+        Some(rest) => format!("{prologue}//{rest}{epilogue}"),
+        None => format!("{prologue}{code}{epilogue}"),
+    }
+}
+
+/// Resolves a relative `require` of a local module the way Node does, by
+/// probing extensions and index files.
+fn probe_relative(required: &str, path: Option<&Path>) -> Option<String> {
+    let resolved = package_json::probe_file(&path?.parent()?.join(required))?;
+    Some(ModuleSpecifier::from_file_path(resolved).ok()?.to_string())
+}
+
+fn is_relative(specifier: &str) -> bool {
+    specifier.starts_with("./") || specifier.starts_with("../")
+}
+
+pub fn js_string(text: &str) -> String {
+    deno_core::serde_json::to_string(text).unwrap()
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Finds occurrences of `needle` that are not part of a longer identifier or
+/// member expression, returning the text following each.
+fn find_standalone<'a>(code: &'a str, needle: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    code.match_indices(needle).filter_map(move |(start, _)| {
+        let preceding = code[..start].chars().next_back();
+        if preceding.is_some_and(|c| is_ident_char(c) || c == '.') {
+            return None;
+        }
+        Some(&code[start + needle.len()..])
+    })
+}
+
+fn read_ident(text: &str) -> Option<&str> {
+    let end = text
+        .char_indices()
+        .find(|(_, c)| !is_ident_char(*c))
+        .map_or(text.len(), |(i, _)| i);
+    let ident = &text[..end];
+    (!ident.is_empty() && !ident.starts_with(|c: char| c.is_ascii_digit())).then_some(ident)
+}
+
+fn read_string_literal(text: &str) -> Option<(&str, &str)> {
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let end = text[1..].find(quote)? + 1;
+    Some((&text[1..end], &text[end + 1..]))
+}
+
+/// The string literal specifiers of the calls to `require` in `code`,
+/// including conditional ones.
+fn required_specifiers(specifier: &ModuleSpecifier, code: &str) -> BTreeSet<String> {
+    let mut collector = RequireCollector::default();
+    if let Ok(parsed) = parse(specifier, code) {
+        parsed.program_ref().visit_with(&mut collector);
+    }
+    collector.specifiers
+}
+
+#[derive(Default)]
+struct RequireCollector {
+    specifiers: BTreeSet<String>,
+}
+
+impl Visit for RequireCollector {
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        let is_require = match &call.callee {
+            Callee::Expr(callee) => {
+                matches!(&**callee, Expr::Ident(ident) if &*ident.sym == "require")
+            }
+            _ => false,
+        };
+        if is_require && call.args.len() == 1 {
+            match &*call.args[0].expr {
+                Expr::Lit(Lit::Str(literal)) => {
+                    self.specifiers.insert(literal.value.to_string());
+                }
+                Expr::Tpl(template) if template.exprs.is_empty() => {
+                    if let Some(cooked) = &template.quasis[0].cooked {
+                        self.specifiers.insert(cooked.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        call.visit_children_with(self);
+    }
+}
+
+/// Detects names assigned to `exports`/`module.exports`, defined on them with
+/// `Object.defineProperty`, or listed in a `module.exports = { ... }` object
+/// literal.
+fn named_exports(code: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let assigned = find_standalone(code, "exports.")
+        .chain(find_standalone(code, "module.exports."))
+        .filter_map(|rest| {
+            let name = read_ident(rest)?;
+            let rest = rest[name.len()..].trim_start();
+            (rest.starts_with('=') && !rest.starts_with("==")).then_some(name)
+        });
+    names.extend(assigned.map(str::to_string));
+    for target in ["exports,", "module.exports,"] {
+        for rest in find_standalone(code, "Object.defineProperty(") {
+            let Some(rest) = rest.trim_start().strip_prefix(target) else {
+                continue;
+            };
+            if let Some((name, _)) = read_string_literal(rest.trim_start()) {
+                names.insert(name.to_string());
+            }
+        }
+    }
+    for rest in find_standalone(code, "module.exports") {
+        let Some(rest) = rest.trim_start().strip_prefix('=') else {
+            continue;
+        };
+        if let Some(object) = rest.trim_start().strip_prefix('{') {
+            names.extend(object_literal_keys(object));
+        }
+    }
+    names.remove("default");
+    names
+}
+
+/// Keys of the object literal starting at `object` (after its `{`), e.g.
+/// `a` and `b` in `{ a, b: 1 }`. Stops at the first key it cannot read.
+fn object_literal_keys(object: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut rest = object;
+    loop {
+        rest = rest.trim_start();
+        let Some(key) = read_ident(rest) else {
+            break;
+        };
+        keys.push(key.to_string());
+        rest = rest[key.len()..].trim_start();
+        if let Some(value) = rest.strip_prefix(':') {
+            // Skip the value up to the next top level comma.
+            let mut depth = 0usize;
+            let mut end = None;
+            for (i, c) in value.char_indices() {
+                match c {
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' if depth == 0 => {
+                        end = Some(i);
+                        break;
+                    }
+                    ')' | ']' | '}' => depth -= 1,
+                    ',' if depth == 0 => {
+                        end = Some(i);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            rest = &value[end.unwrap_or(value.len())..];
+        }
+        match rest.strip_prefix(',') {
+            Some(next) => rest = next,
+            None => break,
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(iter: impl IntoIterator<Item = impl ToString>) -> BTreeSet<String> {
+        iter.into_iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn finds_required_specifiers() {
+        let specifier = ModuleSpecifier::parse("file:///pkg/index.js").unwrap();
+        let code = r#"
+            const a = require("./a");
+            const b = require('b');
+            const c = require(`c/sub`);
+            let d;
+            try { d = require("optional"); } catch {}
+            const e = require(name);
+            const f = require("f", extra);
+            obj.require("member");
+            const s = 'require("in-string")';
+            // require("in-comment")
+        "#;
+        assert_eq!(
+            required_specifiers(&specifier, code),
+            names(["./a", "b", "c/sub", "optional"])
+        );
+        assert!(required_specifiers(&specifier, "require('a'").is_empty());
+    }
+
+    #[test]
+    fn finds_named_exports() {
+        let code = r#"
+            exports.a = 1;
+            module.exports.b = function () {};
+            exports.c == 1;
+            foo.exports.d = 1;
+            Object.defineProperty(exports, "e", { get() { return 1; } });
+            Object.defineProperty(module.exports, 'f', { value: 1 });
+            exports.default = 2;
+        "#;
+        assert_eq!(named_exports(code), names(["a", "b", "e", "f"]));
+
+        let code = "module.exports = { g, h: call(1, 2), i: { j: 1 }, k };";
+        assert_eq!(named_exports(code), names(["g", "h", "i", "k"]));
+        let code = "module.exports = { l, ...rest, m };";
+        assert_eq!(named_exports(code), names(["l"]));
+    }
+
+    #[test]
+    fn wraps_modules() {
+        let specifier = ModuleSpecifier::parse("file:///pkg/index.js").unwrap();
+        let code = "const dep = require('dep');\nconst fs = require('node:fs');\nexports.a = 1;";
+        let wrapped = wrap(code, &specifier, |s| s == "dep");
+        let (first_line, rest) = wrapped.split_once('\n').unwrap();
+        // Line numbers of the wrapped code are unchanged.
+        assert!(first_line.ends_with("const dep = require('dep');"));
+        assert!(first_line.contains(r#"import * as __cjs_dep0 from "dep";"#));
+        assert!(!wrapped.contains(r#"from "node:fs""#));
+        assert!(rest.contains(r#"export { __cjs_export0 as "a" };"#));
+
+        let wrapped = wrap(code, &specifier, |_| false);
+        assert!(wrapped.contains("const __cjs_deps = {};"));
+
+        let code = "#!/usr/bin/env node\nexports.a = 1;";
+        let wrapped = wrap(code, &specifier, |_| false);
+        let (first_line, _) = wrapped.split_once('\n').unwrap();
+        assert!(first_line.ends_with("{///usr/bin/env node"));
+        assert!(!wrapped.contains("#!"));
+    }
+}
@@ -0,0 +1,89 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use deno_ast::EmitOptions;
+use deno_core::anyhow;
+use deno_core::serde_json;
+use deno_core::ModuleSpecifier;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+/// On-disk cache of transpiled modules, laid out like `$DENO_DIR/gen`:
+/// `<root>/<sha256 of specifier>.js` holds the emit and a sibling
+/// `.meta.json` records the hash of the source it was emitted from.
+#[derive(Clone, Debug)]
+pub struct EmitCache {
+    root: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    source_hash: String,
+}
+
+impl EmitCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Hash of everything that affects the emit, so that a change to either
+    /// the source or the transpile options invalidates the cached output.
+    pub fn source_hash(source: &str, emit_options: &EmitOptions) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(format!("{emit_options:?}"));
+        hasher.update(source);
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn emit_path(&self, specifier: &ModuleSpecifier) -> PathBuf {
+        self.root.join(format!(
+            "{:x}.js",
+            Sha256::digest(specifier.as_str().as_bytes())
+        ))
+    }
+
+    pub async fn get(
+        &self,
+        specifier: &ModuleSpecifier,
+        source_hash: &str,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let path = self.emit_path(specifier);
+        let metadata = match tokio::fs::read(path.with_extension("meta.json")).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let metadata: Metadata = serde_json::from_slice(&metadata)?;
+        if metadata.source_hash != source_hash {
+            return Ok(None);
+        }
+        match tokio::fs::read_to_string(&path).await {
+            Ok(code) => Ok(Some(code)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        specifier: &ModuleSpecifier,
+        source_hash: &str,
+        code: &str,
+    ) -> Result<(), anyhow::Error> {
+        let path = self.emit_path(specifier);
+        tokio::fs::create_dir_all(&self.root).await?;
+        tokio::fs::write(&path, code).await?;
+        let metadata = Metadata {
+            source_hash: source_hash.to_string(),
+        };
+        tokio::fs::write(
+            path.with_extension("meta.json"),
+            serde_json::to_vec(&metadata)?,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+
+use deno_ast::Diagnostic;
+use deno_ast::MediaType;
+use deno_ast::SourceTextInfo;
+use deno_core::ModuleSpecifier;
+
+/// The failures of [`crate::TypescriptModuleLoader`] that callers may want to
+/// tell apart. They are returned inside the `anyhow::Error` of `resolve` and
+/// `load` and can be recovered with `error.downcast_ref::<LoaderError>()`;
+/// other failures, such as I/O errors, are passed through as they are.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LoaderError {
+    /// A local module, a file of an npm package, or an npm or JSR package
+    /// version or export that does not exist.
+    NotFound {
+        specifier: ModuleSpecifier,
+    },
+    /// A remote module or package that is not cached, in cached-only mode.
+    NotCached {
+        specifier: String,
+    },
+    /// A remote module or package served with a non-success status.
+    HttpStatus {
+        specifier: ModuleSpecifier,
+        status: u16,
+    },
+    TooManyRedirects {
+        specifier: ModuleSpecifier,
+    },
+    UnsupportedScheme {
+        specifier: ModuleSpecifier,
+    },
+    UnsupportedMediaType {
+        specifier: ModuleSpecifier,
+        media_type: MediaType,
+    },
+    /// A module whose content does not match its media type, e.g. invalid
+    /// UTF-8, JSON or WebAssembly.
+    InvalidContent {
+        specifier: ModuleSpecifier,
+        message: String,
+    },
+    /// Syntax errors, displayed with a frame of the source code around each.
+    Parse {
+        specifier: ModuleSpecifier,
+        diagnostics: Vec<Diagnostic>,
+        /// The source the diagnostics refer to.
+        source: String,
+    },
+    Transpile {
+        specifier: ModuleSpecifier,
+        message: String,
+    },
+    PermissionDenied {
+        message: String,
+    },
+    /// A remote module or npm package whose checksum differs from the
+    /// expected one.
+    IntegrityMismatch {
+        specifier: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl LoaderError {
+    /// The class of the JS error this should surface as, for use in the
+    /// runtime's `get_error_class_fn`.
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::NotFound { .. } | Self::NotCached { .. } => "NotFound",
+            Self::HttpStatus { .. } | Self::TooManyRedirects { .. } => "Http",
+            Self::UnsupportedScheme { .. } | Self::UnsupportedMediaType { .. } => "TypeError",
+            Self::InvalidContent { .. } | Self::Parse { .. } | Self::Transpile { .. } => {
+                "SyntaxError"
+            }
+            Self::PermissionDenied { .. } => "PermissionDenied",
+            Self::IntegrityMismatch { .. } => "Error",
+        }
+    }
+}
+
+impl fmt::Display for LoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { specifier } => write!(f, "Module not found: {specifier}"),
+            Self::NotCached { specifier } => write!(
+                f,
+                "Specifier not found in cache: \"{specifier}\", cached-only mode is enabled"
+            ),
+            Self::HttpStatus { specifier, status } => {
+                write!(f, "Failed to fetch {specifier}: status {status}")
+            }
+            Self::TooManyRedirects { specifier } => {
+                write!(f, "Too many redirects while fetching {specifier}")
+            }
+            Self::UnsupportedScheme { specifier } => {
+                write!(f, "Unsupported module specifier: {specifier}")
+            }
+            Self::UnsupportedMediaType {
+                specifier,
+                media_type,
+            } => write!(f, "Unsupported media type {media_type} of {specifier}"),
+            Self::InvalidContent { specifier, message } => {
+                write!(f, "Invalid module {specifier}: {message}")
+            }
+            Self::Parse {
+                diagnostics,
+                source,
+                ..
+            } => {
+                let text_info = SourceTextInfo::from_string(source.clone());
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "\n\n")?;
+                    }
+                    write_code_frame(f, diagnostic, &text_info)?;
+                }
+                Ok(())
+            }
+            Self::Transpile { specifier, message } => {
+                write!(f, "Failed to transpile {specifier}: {message}")
+            }
+            Self::PermissionDenied { message } => write!(f, "{message}"),
+            Self::IntegrityMismatch {
+                specifier,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Integrity check failed for {specifier}\n  Expected: {expected}\n  Actual: {actual}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoaderError {}
+
+/// Writes a diagnostic the way `deno check` does: the message, the offending
+/// line with the range underlined, then the location.
+///
+/// ```text
+/// error: Expected ';', '}' or <eof>
+/// let a b = 1;
+///       ~
+///     at file:///main.ts:1:7
+/// ```
+fn write_code_frame(
+    f: &mut fmt::Formatter<'_>,
+    diagnostic: &Diagnostic,
+    text_info: &SourceTextInfo,
+) -> fmt::Result {
+    let start = text_info.line_and_column_index(diagnostic.range.start);
+    let end = text_info.line_and_column_index(diagnostic.range.end);
+    let line = text_info.line_text(start.line_index);
+    // The column indexes count bytes, and the frame is laid out in chars.
+    let start_column = char_column(line, start.column_index);
+    // Underline to the end of the line when the range spans several lines.
+    let end_column = if end.line_index == start.line_index {
+        char_column(line, end.column_index)
+    } else {
+        line.chars().count()
+    };
+    // Keeps tabs so the underline lines up with the code above it.
+    let indent: String = line
+        .chars()
+        .take(start_column)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let underline = "~".repeat(end_column.saturating_sub(start_column).max(1));
+    write!(
+        f,
+        "error: {}\n{line}\n{indent}{underline}\n    at {}:{}:{}",
+        diagnostic.message(),
+        diagnostic.specifier,
+        diagnostic.display_position.line_number,
+        diagnostic.display_position.column_number
+    )
+}
+
+/// The number of chars before byte `byte_column` of `line`.
+fn char_column(line: &str, byte_column: usize) -> usize {
+    line.char_indices()
+        .take_while(|(i, _)| *i < byte_column)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use deno_ast::ParseParams;
+
+    use super::*;
+
+    #[test]
+    fn displays_parse_errors_with_a_code_frame() {
+        let specifier = ModuleSpecifier::parse("file:///main.ts").unwrap();
+        let source = "const café = \"☕\"; let a b = 1;\n".to_string();
+        let diagnostics = match deno_ast::parse_module(ParseParams {
+            specifier: specifier.to_string(),
+            text_info: SourceTextInfo::from_string(source.clone()),
+            media_type: MediaType::TypeScript,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        }) {
+            Ok(parsed) => parsed.diagnostics().clone(),
+            Err(diagnostic) => vec![diagnostic],
+        };
+        assert_eq!(diagnostics.len(), 1);
+        let error = LoaderError::Parse {
+            specifier,
+            diagnostics,
+            source,
+        };
+        assert_eq!(error.class(), "SyntaxError");
+
+        let text = error.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4, "{text}");
+        assert!(lines[0].starts_with("error: "));
+        assert_eq!(lines[1], "const café = \"☕\"; let a b = 1;");
+        // The underline is under `b`, after non-ASCII chars.
+        let indent = " ".repeat("const café = \"☕\"; let a ".chars().count());
+        assert_eq!(lines[2], format!("{indent}~"));
+        assert!(lines[3].starts_with("    at file:///main.ts:1:"));
+    }
+}
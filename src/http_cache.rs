@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use deno_core::anyhow;
+use deno_core::serde_json;
+use deno_core::ModuleSpecifier;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+/// On-disk cache of remote modules, laid out like `$DENO_DIR/deps`:
+/// `<root>/<scheme>/<host>/<sha256 of path and query>` holds the body and a
+/// sibling `.metadata.json` holds the url and response headers.
+#[derive(Clone, Debug)]
+pub struct HttpCache {
+    root: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    url: String,
+    headers: HashMap<String, String>,
+}
+
+pub struct CachedModule {
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn cache_path(&self, specifier: &ModuleSpecifier) -> PathBuf {
+        let host = match (specifier.host_str(), specifier.port()) {
+            (Some(host), Some(port)) => format!("{host}_PORT{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => "_".to_string(),
+        };
+        let mut rest = specifier.path().to_string();
+        if let Some(query) = specifier.query() {
+            rest.push('?');
+            rest.push_str(query);
+        }
+        self.root
+            .join(specifier.scheme())
+            .join(host)
+            .join(format!("{:x}", Sha256::digest(rest.as_bytes())))
+    }
+
+    pub async fn get(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> Result<Option<CachedModule>, anyhow::Error> {
+        let path = self.cache_path(specifier);
+        let metadata = match tokio::fs::read(path.with_extension("metadata.json")).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let metadata: Metadata = serde_json::from_slice(&metadata)?;
+        let body = match tokio::fs::read(&path).await {
+            Ok(body) => body,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(CachedModule {
+            headers: metadata.headers,
+            body,
+        }))
+    }
+
+    pub async fn set(
+        &self,
+        specifier: &ModuleSpecifier,
+        headers: HashMap<String, String>,
+        body: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        let path = self.cache_path(specifier);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write the body first so a metadata file never points at a missing body.
+        tokio::fs::write(&path, body).await?;
+        let metadata = Metadata {
+            url: specifier.to_string(),
+            headers,
+        };
+        tokio::fs::write(
+            path.with_extension("metadata.json"),
+            serde_json::to_vec_pretty(&metadata)?,
+        )
+        .await?;
+        Ok(())
+    }
+}
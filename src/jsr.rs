@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use deno_core::anyhow;
+use deno_core::serde_json;
+use deno_core::serde_json::Value;
+use deno_core::ModuleSpecifier;
+use semver::Version;
+
+use crate::package_req::parse_version_req;
+use crate::package_req::PackageReq;
+use crate::LoaderError;
+use crate::TypescriptModuleLoader;
+
+impl TypescriptModuleLoader {
+    /// Looks up the version and export a `jsr:` specifier refers to and
+    /// returns the `https:` URL of the module.
+    ///
+    /// This needs the registry, so unlike other specifiers it happens when
+    /// the module is loaded rather than in the synchronous `resolve`.
+    pub(crate) async fn resolve_jsr_specifier(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> Result<ModuleSpecifier, anyhow::Error> {
+        let req = PackageReq::parse(specifier)?;
+        if !req.name.starts_with('@') {
+            bail!("JSR packages must be scoped: {specifier}");
+        }
+        let package_url = self.jsr_url.join(&format!("{}/", req.name))?;
+        let meta = self
+            .jsr_package_meta(&package_url.join("meta.json")?)
+            .await?;
+        let version_req = req.version_req.as_deref();
+        let not_found = || LoaderError::NotFound {
+            specifier: specifier.clone(),
+        };
+        let version = select_version(&meta, version_req).ok_or_else(not_found)?;
+        let version_meta = self
+            .fetch_json(&package_url.join(&format!("{version}_meta.json"))?)
+            .await?;
+        let Some(export) = version_meta["exports"][&req.sub_path].as_str() else {
+            return Err(not_found().into());
+        };
+        Ok(package_url.join(&format!("{version}/{}", export.trim_start_matches("./")))?)
+    }
+
+    /// Fetches the list of versions of a package, which changes as versions
+    /// are published, so it is only read from the cache in cached-only mode.
+    async fn jsr_package_meta(&self, url: &ModuleSpecifier) -> Result<Value, anyhow::Error> {
+        if self.cached_only {
+            return self.fetch_json(url).await;
+        }
+        let body = self.fetch_following_redirects(url).await?.bytes().await?;
+        self.http_cache.set(url, HashMap::new(), &body).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Fetches JSON that never changes once published, through the cache.
+    async fn fetch_json(&self, url: &ModuleSpecifier) -> Result<Value, anyhow::Error> {
+        let remote = self.fetch_remote(url).await?;
+        Ok(serde_json::from_slice(&remote.body)?)
+    }
+}
+
+/// Picks the highest version in range that was not yanked, preferring the
+/// latest version when it is in range.
+fn select_version(meta: &Value, version_req: Option<&str>) -> Option<String> {
+    let ranges = parse_version_req(version_req.unwrap_or("*"))?;
+    let matches = |version: &Version| ranges.iter().any(|range| range.matches(version));
+    if let Some(latest) = meta["latest"].as_str().and_then(|v| Version::parse(v).ok()) {
+        if matches(&latest) {
+            return Some(latest.to_string());
+        }
+    }
+    meta["versions"]
+        .as_object()?
+        .iter()
+        .filter(|(_, info)| !info["yanked"].as_bool().unwrap_or(false))
+        .filter_map(|(version, _)| Version::parse(version).ok())
+        .filter(matches)
+        .max()
+        .map(|version| version.to_string())
+}
@@ -1,31 +1,327 @@
+mod cjs;
+mod emit_cache;
+mod error;
+mod http_cache;
+mod jsr;
+mod lockfile;
+mod node_resolver;
+mod npm;
+mod package_json;
+mod package_req;
+mod permissions;
+mod prefetch;
+mod raw_imports;
+mod scheme_handler;
+mod source_maps;
+mod transpile_options;
+mod wasm;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
 use deno_core::futures::FutureExt;
 use deno_core::{resolve_import, ModuleLoader};
 
-use anyhow::bail;
+use deno_ast::EmitOptions;
 use deno_ast::MediaType;
 use deno_ast::ParseParams;
 use deno_ast::SourceTextInfo;
+use deno_core::serde_json;
+use deno_core::serde_json::Value;
 use deno_core::ModuleCode;
 use deno_core::ModuleSource;
+use deno_core::ModuleSpecifier;
 use deno_core::ModuleType;
 use deno_core::{anyhow, error::generic_error};
+use import_map::ImportMap;
+use import_map::ImportMapError;
+use serde::de::IgnoredAny;
+
+use emit_cache::EmitCache;
+use http_cache::HttpCache;
+use npm::NpmRegistry;
+use prefetch::Prefetched;
+use scheme_handler::DataUrlHandler;
+use scheme_handler::FileHandler;
+use scheme_handler::HttpHandler;
+use scheme_handler::JsrHandler;
+use scheme_handler::NpmHandler;
 
+pub use error::LoaderError;
+pub use lockfile::Lockfile;
+pub use permissions::HostRule;
+pub use permissions::Permissions;
+pub use raw_imports::RawImportType;
+pub use scheme_handler::FetchedModule;
+pub use scheme_handler::SchemeHandler;
+pub use source_maps::EmittedSourceMaps;
+pub use transpile_options::JsxRuntime;
+pub use transpile_options::TranspileOptions;
+
+#[derive(Clone)]
 pub struct TypescriptModuleLoader {
     http: reqwest::Client,
+    http_cache: HttpCache,
+    emit_cache: EmitCache,
+    lockfile: Option<Arc<Mutex<Lockfile>>>,
+    cached_only: bool,
+    import_map: Option<Arc<ImportMap>>,
+    source_maps: EmittedSourceMaps,
+    emit_options: EmitOptions,
+    permissions: Arc<Permissions>,
+    media_type_overrides: Arc<Vec<MediaTypeOverride>>,
+    npm_registry: NpmRegistry,
+    /// A lock per npm package version being installed.
+    npm_installs: Arc<Mutex<HashMap<String, Arc<deno_core::futures::lock::Mutex<()>>>>>,
+    jsr_url: ModuleSpecifier,
+    virtual_modules: Arc<RwLock<HashMap<ModuleSpecifier, VirtualModule>>>,
+    scheme_handlers: Arc<HashMap<String, Arc<dyn SchemeHandler>>>,
+    raw_imports: Arc<HashMap<String, RawImportType>>,
+    prefetched: Arc<Mutex<Prefetched>>,
+    /// Modules without a location of their own that were imported by remote
+    /// modules, and are treated as remote themselves.
+    remote_origin: Arc<Mutex<HashSet<String>>>,
+}
+
+struct VirtualModule {
+    code: String,
+    media_type: MediaType,
+}
+
+/// Overrides the content-type of remote modules matching `rule`. With no
+/// `media_type`, the media type is taken from the extension of the URL.
+#[derive(Clone, Debug)]
+pub struct MediaTypeOverride {
+    pub rule: HostRule,
+    pub media_type: Option<MediaType>,
+}
+
+/// Where to read an import map from. Relative addresses in the map resolve
+/// against the file or URL it was read from, or against `base_url` for an
+/// in-memory map.
+pub enum ImportMapSource {
+    Path(PathBuf),
+    Url(ModuleSpecifier),
+    Value {
+        base_url: ModuleSpecifier,
+        value: Value,
+    },
 }
 
 impl Default for TypescriptModuleLoader {
     fn default() -> Self {
-        Self {
-            http: reqwest::Client::new(),
-        }
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        Self::new(http, default_cache_dir())
     }
 }
 
 impl TypescriptModuleLoader {
-    pub fn new(http: reqwest::Client) -> Self {
-        Self { http }
+    /// Remote modules and transpiled output are cached under `cache_dir` and
+    /// served from there on subsequent loads instead of being fetched or
+    /// transpiled again.
+    ///
+    /// `http` must not follow redirects itself (see
+    /// [`reqwest::redirect::Policy::none`]): the loader follows them so that
+    /// net permissions are checked before each request is sent.
+    pub fn new(http: reqwest::Client, cache_dir: impl Into<PathBuf>) -> Self {
+        let cache_dir = cache_dir.into();
+        Self {
+            http,
+            http_cache: HttpCache::new(cache_dir.join("deps")),
+            emit_cache: EmitCache::new(cache_dir.join("gen")),
+            lockfile: None,
+            cached_only: false,
+            import_map: None,
+            source_maps: EmittedSourceMaps::default(),
+            emit_options: TranspileOptions::default().emit_options(),
+            permissions: Arc::new(Permissions::default()),
+            media_type_overrides: Arc::new(Vec::new()),
+            npm_registry: NpmRegistry::new(
+                ModuleSpecifier::parse("https://registry.npmjs.org/").unwrap(),
+                cache_dir.join("npm"),
+            ),
+            npm_installs: Default::default(),
+            jsr_url: ModuleSpecifier::parse("https://jsr.io/").unwrap(),
+            virtual_modules: Default::default(),
+            scheme_handlers: Default::default(),
+            raw_imports: Default::default(),
+            prefetched: Default::default(),
+            remote_origin: Default::default(),
+        }
+    }
+
+    /// Checks every remote module against `lockfile` before it is evaluated.
+    pub fn with_lockfile(mut self, lockfile: Lockfile) -> Self {
+        self.lockfile = Some(Arc::new(Mutex::new(lockfile)));
+        self
+    }
+
+    /// Serves remote modules only from the cache and never touches the
+    /// network, failing on modules that have not been cached yet.
+    pub fn with_cached_only(mut self, cached_only: bool) -> Self {
+        self.cached_only = cached_only;
+        self
+    }
+
+    /// Transpiles TypeScript and JSX modules with the given options, e.g. ones
+    /// read with [`TranspileOptions::from_config_file`].
+    pub fn with_transpile_options(mut self, options: TranspileOptions) -> Self {
+        self.emit_options = options.emit_options();
+        self
+    }
+
+    /// Restricts which modules may be loaded; both `resolve` and `load` fail
+    /// with [`LoaderError::PermissionDenied`] for modules that are not
+    /// permitted.
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = Arc::new(permissions);
+        self
+    }
+
+    /// Ignores the content-type of remote modules matching an override, for
+    /// hosts that leave it out or send a generic one such as `text/plain`.
+    pub fn with_media_type_overrides(mut self, overrides: Vec<MediaTypeOverride>) -> Self {
+        self.media_type_overrides = Arc::new(overrides);
+        self
+    }
+
+    /// Downloads `npm:` packages from the given registry instead of
+    /// `https://registry.npmjs.org/`.
+    pub fn with_npm_registry(mut self, url: ModuleSpecifier) -> Self {
+        self.npm_registry.set_url(url);
+        self
+    }
+
+    /// Resolves `jsr:` specifiers against the given registry instead of
+    /// `https://jsr.io/`.
+    ///
+    /// `resolve` leaves `jsr:` specifiers as they are, since looking up the
+    /// version needs the network. `load` maps them to the `https:` URL of the
+    /// module and reports that URL as the module's redirect, so relative
+    /// imports resolve against the registry.
+    pub fn with_jsr_registry(mut self, mut url: ModuleSpecifier) -> Self {
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        self.jsr_url = url;
+        self
+    }
+
+    /// Loads modules of `scheme` (without the trailing colon) through
+    /// `handler`. This takes precedence over the built-in handling of
+    /// `file:`, `http:`, `https:`, `data:`, `npm:` and `jsr:` specifiers.
+    pub fn with_scheme_handler(
+        mut self,
+        scheme: impl Into<String>,
+        handler: impl SchemeHandler + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.scheme_handlers).insert(scheme.into(), Arc::new(handler));
+        self
+    }
+
+    /// Serves files with the given extension (without the leading dot, e.g.
+    /// `sql`) as a module whose default export is their text or bytes,
+    /// whatever their media type.
+    ///
+    /// Import attributes such as `with { type: "text" }` cannot select this
+    /// instead, since deno_core only accepts the `json` type and does not
+    /// pass attributes on to the loader.
+    pub fn with_raw_import(
+        mut self,
+        extension: impl Into<String>,
+        import_type: RawImportType,
+    ) -> Self {
+        Arc::make_mut(&mut self.raw_imports).insert(extension.into(), import_type);
+        self
     }
+
+    /// Remaps specifiers through the given import map (imports and scopes)
+    /// before falling back to regular URL resolution.
+    ///
+    /// A map read from a URL is cached like remote modules, and is only read
+    /// from the cache in cached-only mode, so call
+    /// [`Self::with_cached_only`] and [`Self::with_permissions`] first.
+    pub async fn with_import_map(mut self, source: ImportMapSource) -> Result<Self, anyhow::Error> {
+        let import_map = match source {
+            ImportMapSource::Path(path) => {
+                let path = tokio::fs::canonicalize(&path).await?;
+                let base_url = ModuleSpecifier::from_file_path(&path).map_err(|_| {
+                    generic_error(format!("Invalid import map path: {}", path.display()))
+                })?;
+                let text = tokio::fs::read_to_string(&path).await?;
+                import_map::parse_from_json(&base_url, &text)?
+            }
+            ImportMapSource::Url(url) => {
+                let remote = self.fetch_remote(&url).await?;
+                let text =
+                    String::from_utf8(remote.body).map_err(|_| LoaderError::InvalidContent {
+                        specifier: remote.specifier.clone(),
+                        message: "not valid UTF-8".to_string(),
+                    })?;
+                // Relative addresses resolve against where the map was found.
+                import_map::parse_from_json(&remote.specifier, &text)?
+            }
+            ImportMapSource::Value { base_url, value } => {
+                import_map::parse_from_value(&base_url, value)?
+            }
+        };
+        self.import_map = Some(Arc::new(import_map.import_map));
+        Ok(self)
+    }
+
+    /// Serves `code` for `specifier` before looking at the file system or the
+    /// network. Any scheme can be used, e.g. `virtual:///lib/mod.ts`; relative
+    /// imports between virtual modules need a hierarchical URL like that one.
+    ///
+    /// This also affects clones of the loader, including one already handed
+    /// to a runtime, but not modules the runtime has already loaded.
+    pub fn register_virtual_module(
+        &self,
+        specifier: ModuleSpecifier,
+        code: impl Into<String>,
+        media_type: MediaType,
+    ) {
+        self.prefetched.lock().unwrap().discard(&specifier);
+        self.virtual_modules.write().unwrap().insert(
+            specifier,
+            VirtualModule {
+                code: code.into(),
+                media_type,
+            },
+        );
+    }
+
+    /// Removes a module added with [`Self::register_virtual_module`],
+    /// returning whether it was registered.
+    pub fn unregister_virtual_module(&self, specifier: &ModuleSpecifier) -> bool {
+        self.prefetched.lock().unwrap().discard(specifier);
+        self.virtual_modules
+            .write()
+            .unwrap()
+            .remove(specifier)
+            .is_some()
+    }
+
+    /// Source maps of the modules transpiled by this loader, to be passed to
+    /// the runtime as its `source_map_getter`.
+    pub fn source_maps(&self) -> EmittedSourceMaps {
+        self.source_maps.clone()
+    }
+}
+
+/// The platform cache location used by [`TypescriptModuleLoader::default`],
+/// e.g. `~/.cache/basic_deno_ts_module_loader` on Linux.
+pub fn default_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("basic_deno_ts_module_loader")
 }
 
 impl ModuleLoader for TypescriptModuleLoader {
@@ -35,7 +331,46 @@ impl ModuleLoader for TypescriptModuleLoader {
         referrer: &str,
         _kind: deno_core::ResolutionKind,
     ) -> Result<deno_core::ModuleSpecifier, anyhow::Error> {
-        Ok(resolve_import(specifier, referrer)?)
+        let mapped = match &self.import_map {
+            Some(import_map) => {
+                let referrer_url = ModuleSpecifier::parse(referrer)
+                    .unwrap_or_else(|_| import_map.base_url().clone());
+                match import_map.resolve(specifier, &referrer_url) {
+                    Ok(resolved) => Some(resolved),
+                    // Only bare specifiers the map knows nothing about fall
+                    // through; e.g. specifiers it maps to `null` are blocked.
+                    Err(ImportMapError::UnmappedBareSpecifier(..)) => None,
+                    Err(e) => return Err(generic_error(e.to_string())),
+                }
+            }
+            None => None,
+        };
+        let resolved = match mapped {
+            Some(resolved) => resolved,
+            None => match resolve_import(specifier, referrer) {
+                Ok(resolved) => resolved,
+                Err(err) => match self.resolve_npm_dependency(specifier, referrer) {
+                    Some(resolved) => resolved,
+                    None => node_resolver::resolve(specifier, referrer)?.ok_or(err)?,
+                },
+            },
+        };
+        if matches!(resolved.scheme(), "http" | "https") {
+            self.permissions.check_net(&resolved)?;
+        }
+        let referrer_is_remote = matches!(referrer.split_once(':'), Some(("http" | "https", _)))
+            || self.remote_origin.lock().unwrap().contains(referrer);
+        self.permissions
+            .check_import(&resolved, referrer, referrer_is_remote)?;
+        // Modules without a location of their own, like `data:` URLs, are as
+        // remote as the module that imports them.
+        if referrer_is_remote && resolved.cannot_be_a_base() {
+            self.remote_origin
+                .lock()
+                .unwrap()
+                .insert(resolved.to_string());
+        }
+        Ok(resolved)
     }
 
     fn load(
@@ -44,89 +379,443 @@ impl ModuleLoader for TypescriptModuleLoader {
         _maybe_referrer: Option<&deno_core::ModuleSpecifier>,
         _is_dyn_import: bool,
     ) -> std::pin::Pin<Box<deno_core::ModuleSourceFuture>> {
+        let prefetched = self.prefetched.lock().unwrap().take(module_specifier);
+        let loader = self.clone();
         let module_specifier = module_specifier.clone();
-        let http = self.http.clone();
         async move {
-            let (code, module_type, media_type, should_transpile) = match module_specifier
-                .to_file_path()
-            {
-                Ok(path) => {
-                    let media_type = MediaType::from_path(&path);
-                    let (module_type, should_transpile) = match media_type {
-                        MediaType::JavaScript | MediaType::Mjs | MediaType::Cjs => {
-                            (ModuleType::JavaScript, false)
-                        }
-                        MediaType::Jsx => (ModuleType::JavaScript, true),
-                        MediaType::TypeScript
-                        | MediaType::Mts
-                        | MediaType::Cts
-                        | MediaType::Dts
-                        | MediaType::Dmts
-                        | MediaType::Dcts
-                        | MediaType::Tsx => (ModuleType::JavaScript, true),
-                        MediaType::Json => (ModuleType::Json, false),
-                        _ => bail!("Unknown extension {:?}", path.extension()),
-                    };
+            let module = match prefetched {
+                Some(module) => module,
+                None => loader.load_module(&module_specifier).await?,
+            };
+            Ok(module.into_module_source(&module_specifier))
+        }
+        .boxed_local()
+    }
+
+    /// Loads the static imports of the module graph ahead of deno_core, which
+    /// would otherwise only discover each module's imports once it is loaded.
+    fn prepare_load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        _maybe_referrer: Option<String>,
+        _is_dyn_import: bool,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), anyhow::Error>>>> {
+        let loader = self.clone();
+        let module_specifier = module_specifier.clone();
+        async move {
+            loader.prefetch_graph(module_specifier).await;
+            Ok(())
+        }
+        .boxed_local()
+    }
+}
+
+/// A remote module as it was served, after following redirects.
+struct RemoteModule {
+    specifier: ModuleSpecifier,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
 
-                    (
-                        tokio::fs::read_to_string(&path).await?,
-                        module_type,
+/// The response to a request for a remote module that was not cached.
+enum RemoteResponse {
+    Module(RemoteModule),
+    Redirect(ModuleSpecifier),
+}
+
+/// A module's source as it was found, before it is transpiled.
+struct LoadedSource {
+    specifier: ModuleSpecifier,
+    code: String,
+    module_type: ModuleType,
+    media_type: MediaType,
+    should_transpile: bool,
+}
+
+/// A module ready to be handed to deno_core.
+struct LoadedModule {
+    specifier: ModuleSpecifier,
+    code: Box<str>,
+    module_type: ModuleType,
+}
+
+impl LoadedModule {
+    fn into_module_source(self, module_specifier: &ModuleSpecifier) -> ModuleSource {
+        let code = ModuleCode::Owned(self.code);
+        if self.specifier == *module_specifier {
+            ModuleSource::new(self.module_type, code, module_specifier)
+        } else {
+            // Lets deno_core resolve the module's own imports against the URL
+            // it was actually served from.
+            ModuleSource::new_with_redirect(
+                self.module_type,
+                code,
+                module_specifier,
+                &self.specifier,
+            )
+        }
+    }
+}
+
+const MAX_REDIRECTS: usize = 10;
+
+impl TypescriptModuleLoader {
+    async fn load_module(
+        &self,
+        module_specifier: &ModuleSpecifier,
+    ) -> Result<LoadedModule, anyhow::Error> {
+        let virtual_module = self
+            .virtual_modules
+            .read()
+            .unwrap()
+            .get(module_specifier)
+            .map(|module| (module.code.clone(), module.media_type));
+        let source = match virtual_module {
+            Some((code, media_type)) => {
+                let Some((module_type, should_transpile)) = module_type(media_type) else {
+                    return Err(LoaderError::UnsupportedMediaType {
+                        specifier: module_specifier.clone(),
                         media_type,
-                        should_transpile,
-                    )
+                    }
+                    .into());
+                };
+                LoadedSource {
+                    specifier: module_specifier.clone(),
+                    code,
+                    module_type,
+                    media_type,
+                    should_transpile,
+                }
+            }
+            None => self.load_source(module_specifier).await?,
+        };
+        let LoadedSource {
+            specifier: found_specifier,
+            code,
+            module_type,
+            media_type,
+            should_transpile,
+        } = source;
+
+        // deno_core rejects modules whose type differs from the one asserted
+        // by the importer (`assert { type: "json" }`), so the type reported
+        // here has to match the content. A script served with a JSON
+        // content-type fails here rather than being loaded as data.
+        if module_type == ModuleType::Json {
+            if let Err(e) = serde_json::from_str::<IgnoredAny>(&code) {
+                return Err(LoaderError::InvalidContent {
+                    specifier: found_specifier,
+                    message: format!("served as {media_type} but is not JSON: {e}"),
                 }
-                Err(_) => {
-                    if module_specifier.scheme() == "http" || module_specifier.scheme() == "https" {
-                        let http_res = http.get(module_specifier.to_string()).send().await?;
-                        if !http_res.status().is_success() {
-                            bail!("Failed to fetch module: {module_specifier}");
+                .into());
+            }
+        }
+
+        let is_cjs = module_type == ModuleType::JavaScript
+            && cjs::is_cjs(&found_specifier, media_type, &code);
+        let code = if should_transpile {
+            let source_hash = EmitCache::source_hash(&code, &self.emit_options);
+            let emitted = match self.emit_cache.get(&found_specifier, &source_hash).await? {
+                Some(emitted) => emitted,
+                None => {
+                    let parse_error = |diagnostics| LoaderError::Parse {
+                        specifier: found_specifier.clone(),
+                        diagnostics,
+                        source: code.clone(),
+                    };
+                    let parsed = deno_ast::parse_module(ParseParams {
+                        specifier: found_specifier.to_string(),
+                        text_info: SourceTextInfo::from_string(code.clone()),
+                        media_type,
+                        capture_tokens: false,
+                        scope_analysis: false,
+                        maybe_syntax: None,
+                    })
+                    .map_err(|diagnostic| parse_error(vec![diagnostic]))?;
+                    let emitted = match parsed.transpile(&self.emit_options) {
+                        Ok(transpiled) => transpiled.text,
+                        // Transpiling fails on syntax errors the parser
+                        // recovered from, so report all of them.
+                        Err(_) if !parsed.diagnostics().is_empty() => {
+                            return Err(parse_error(parsed.diagnostics().clone()).into());
                         }
-                        let content_type = http_res
-                            .headers()
-                            .get("content-type")
-                            .and_then(|ct| ct.to_str().ok())
-                            .ok_or_else(|| generic_error("No content-type header"))?;
-                        let media_type =
-                            MediaType::from_content_type(&module_specifier, content_type);
-                        let (module_type, should_transpile) = match media_type {
-                            MediaType::JavaScript | MediaType::Mjs | MediaType::Cjs => {
-                                (ModuleType::JavaScript, false)
+                        Err(e) => {
+                            return Err(LoaderError::Transpile {
+                                specifier: found_specifier.clone(),
+                                message: e.to_string(),
                             }
-                            MediaType::Jsx => (ModuleType::JavaScript, true),
-                            MediaType::TypeScript
-                            | MediaType::Mts
-                            | MediaType::Cts
-                            | MediaType::Dts
-                            | MediaType::Dmts
-                            | MediaType::Dcts
-                            | MediaType::Tsx => (ModuleType::JavaScript, true),
-                            MediaType::Json => (ModuleType::Json, false),
-                            _ => bail!("Unknown content-type {:?}", content_type),
-                        };
-                        let code = http_res.text().await?;
-                        (code, module_type, media_type, should_transpile)
-                    } else {
-                        bail!("Unsupported module specifier: {}", module_specifier);
-                    }
+                            .into());
+                        }
+                    };
+                    self.emit_cache
+                        .set(&found_specifier, &source_hash, &emitted)
+                        .await?;
+                    emitted
                 }
             };
-            let code = if should_transpile {
-                let parsed = deno_ast::parse_module(ParseParams {
-                    specifier: module_specifier.to_string(),
-                    text_info: SourceTextInfo::from_string(code),
-                    media_type,
-                    capture_tokens: false,
-                    scope_analysis: false,
-                    maybe_syntax: None,
-                })?;
-                parsed.transpile(&Default::default())?.text.into_boxed_str()
-            } else {
-                code.into_boxed_str()
+            self.source_maps
+                .insert(found_specifier.as_str(), &emitted, &code);
+            emitted.into_boxed_str()
+        } else {
+            code.into_boxed_str()
+        };
+
+        let code = if is_cjs {
+            let can_resolve = |required: &str| {
+                self.resolve(
+                    required,
+                    found_specifier.as_str(),
+                    deno_core::ResolutionKind::Import,
+                )
+                .is_ok()
             };
+            cjs::wrap(&code, &found_specifier, can_resolve).into_boxed_str()
+        } else {
+            code
+        };
+        Ok(LoadedModule {
+            specifier: found_specifier,
+            code,
+            module_type,
+        })
+    }
 
-            let module = ModuleSource::new(module_type, ModuleCode::Owned(code), &module_specifier);
-            Ok(module)
+    async fn load_source(
+        &self,
+        module_specifier: &ModuleSpecifier,
+    ) -> Result<LoadedSource, anyhow::Error> {
+        let scheme = module_specifier.scheme();
+        let fetched = match self.scheme_handlers.get(scheme) {
+            Some(handler) => handler.fetch(module_specifier).await?,
+            None => match scheme {
+                "file" => FileHandler { loader: self }.fetch(module_specifier).await?,
+                "npm" => NpmHandler { loader: self }.fetch(module_specifier).await?,
+                "data" => DataUrlHandler.fetch(module_specifier).await?,
+                "http" | "https" => HttpHandler { loader: self }.fetch(module_specifier).await?,
+                "jsr" => JsrHandler { loader: self }.fetch(module_specifier).await?,
+                _ => {
+                    return Err(LoaderError::UnsupportedScheme {
+                        specifier: module_specifier.clone(),
+                    }
+                    .into())
+                }
+            },
+        };
+        let FetchedModule {
+            specifier,
+            bytes,
+            media_type,
+        } = fetched;
+        let raw_import_type = raw_imports::extension(&specifier)
+            .and_then(|extension| self.raw_imports.get(extension).copied());
+        if let Some(import_type) = raw_import_type {
+            return Ok(LoadedSource {
+                code: raw_imports::wrap(bytes, import_type, &specifier)?,
+                specifier,
+                module_type: ModuleType::JavaScript,
+                media_type,
+                should_transpile: false,
+            });
         }
-        .boxed_local()
+        if media_type == MediaType::Wasm {
+            return Ok(LoadedSource {
+                code: wasm::wrap(&bytes).map_err(|e| LoaderError::InvalidContent {
+                    specifier: specifier.clone(),
+                    message: format!("invalid WebAssembly: {e}"),
+                })?,
+                specifier,
+                module_type: ModuleType::JavaScript,
+                media_type,
+                should_transpile: false,
+            });
+        }
+        let Some((module_type, should_transpile)) = module_type(media_type) else {
+            return Err(LoaderError::UnsupportedMediaType {
+                specifier,
+                media_type,
+            }
+            .into());
+        };
+        Ok(LoadedSource {
+            code: String::from_utf8(bytes).map_err(|_| LoaderError::InvalidContent {
+                specifier: specifier.clone(),
+                message: "not valid UTF-8".to_string(),
+            })?,
+            specifier,
+            module_type,
+            media_type,
+            should_transpile,
+        })
+    }
+
+    /// Serves a remote module from the cache, following cached redirects, and
+    /// fetches it when it is not cached yet.
+    async fn fetch_remote(
+        &self,
+        requested: &ModuleSpecifier,
+    ) -> Result<RemoteModule, anyhow::Error> {
+        let mut specifier = requested.clone();
+        for _ in 0..MAX_REDIRECTS {
+            self.permissions.check_net(&specifier)?;
+            let Some(cached) = self.http_cache.get(&specifier).await? else {
+                match self.fetch_uncached(specifier).await? {
+                    RemoteResponse::Module(module) => return Ok(module),
+                    RemoteResponse::Redirect(location) => {
+                        specifier = location;
+                        continue;
+                    }
+                }
+            };
+            match cached.headers.get("location") {
+                Some(location) => specifier = specifier.join(location)?,
+                None => {
+                    return Ok(RemoteModule {
+                        specifier,
+                        headers: cached.headers,
+                        body: cached.body,
+                    })
+                }
+            }
+        }
+        Err(LoaderError::TooManyRedirects {
+            specifier: requested.clone(),
+        }
+        .into())
+    }
+
+    /// Fetches a single URL and caches the response. Redirects are returned
+    /// rather than followed, so that [`Self::fetch_remote`] can check the
+    /// permissions of each hop before requesting it.
+    async fn fetch_uncached(
+        &self,
+        specifier: ModuleSpecifier,
+    ) -> Result<RemoteResponse, anyhow::Error> {
+        if self.cached_only {
+            return Err(LoaderError::NotCached {
+                specifier: specifier.to_string(),
+            }
+            .into());
+        }
+        let http_res = self.http.get(specifier.clone()).send().await?;
+        // In case the client follows redirects itself despite the docs of
+        // `new`, at least check where it ended up, and report and cache the
+        // response under that URL so relative imports resolve against it.
+        let found = http_res.url().clone();
+        if found != specifier {
+            self.permissions.check_net(&found)?;
+            let headers = HashMap::from([("location".to_string(), found.to_string())]);
+            self.http_cache.set(&specifier, headers, b"").await?;
+        }
+        if let Some(location) = redirect_location(&found, &http_res)? {
+            // Cached like any other response, so later loads follow it
+            // without going to the network.
+            let headers = HashMap::from([("location".to_string(), location.to_string())]);
+            self.http_cache.set(&found, headers, b"").await?;
+            return Ok(RemoteResponse::Redirect(location));
+        }
+        if !http_res.status().is_success() {
+            return Err(LoaderError::HttpStatus {
+                specifier: found,
+                status: http_res.status().as_u16(),
+            }
+            .into());
+        }
+        let headers = http_res
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect::<HashMap<_, _>>();
+        let body = http_res.bytes().await?.to_vec();
+        self.http_cache.set(&found, headers.clone(), &body).await?;
+        Ok(RemoteResponse::Module(RemoteModule {
+            specifier: found,
+            headers,
+            body,
+        }))
+    }
+
+    /// Sends a GET request that is not cached, following redirects after
+    /// checking the net permissions of each hop, and fails on responses
+    /// without a success status.
+    pub(crate) async fn fetch_following_redirects(
+        &self,
+        requested: &ModuleSpecifier,
+    ) -> Result<reqwest::Response, anyhow::Error> {
+        let mut url = requested.clone();
+        for _ in 0..MAX_REDIRECTS {
+            self.permissions.check_net(&url)?;
+            let http_res = self.http.get(url.clone()).send().await?;
+            url = http_res.url().clone();
+            self.permissions.check_net(&url)?;
+            if let Some(location) = redirect_location(&url, &http_res)? {
+                url = location;
+                continue;
+            }
+            if !http_res.status().is_success() {
+                return Err(LoaderError::HttpStatus {
+                    specifier: url,
+                    status: http_res.status().as_u16(),
+                }
+                .into());
+            }
+            return Ok(http_res);
+        }
+        Err(LoaderError::TooManyRedirects {
+            specifier: requested.clone(),
+        }
+        .into())
+    }
+}
+
+/// The absolute URL a redirect response points to.
+fn redirect_location(
+    url: &ModuleSpecifier,
+    http_res: &reqwest::Response,
+) -> Result<Option<ModuleSpecifier>, anyhow::Error> {
+    if !http_res.status().is_redirection() {
+        return Ok(None);
+    }
+    let Some(location) = http_res.headers().get(reqwest::header::LOCATION) else {
+        return Ok(None);
+    };
+    Ok(Some(url.join(location.to_str()?)?))
+}
+
+/// The module type of a supported media type, and whether it needs to be
+/// transpiled first.
+fn module_type(media_type: MediaType) -> Option<(ModuleType, bool)> {
+    match media_type {
+        MediaType::JavaScript | MediaType::Mjs | MediaType::Cjs => {
+            Some((ModuleType::JavaScript, false))
+        }
+        MediaType::Jsx => Some((ModuleType::JavaScript, true)),
+        MediaType::TypeScript
+        | MediaType::Mts
+        | MediaType::Cts
+        | MediaType::Dts
+        | MediaType::Dmts
+        | MediaType::Dcts
+        | MediaType::Tsx => Some((ModuleType::JavaScript, true)),
+        MediaType::Json => Some((ModuleType::Json, false)),
+        _ => None,
+    }
+}
+
+/// Determines the media type of a remote module from the first matching
+/// override, then from its content-type, and falls back to the extension of
+/// the URL when the content-type is missing or not specific enough.
+fn remote_media_type(
+    specifier: &ModuleSpecifier,
+    content_type: Option<&str>,
+    overrides: &[MediaTypeOverride],
+) -> MediaType {
+    if let Some(media_type_override) = overrides.iter().find(|o| o.rule.matches(specifier)) {
+        return media_type_override
+            .media_type
+            .unwrap_or_else(|| MediaType::from_specifier(specifier));
+    }
+    match content_type.map(|content_type| MediaType::from_content_type(specifier, content_type)) {
+        Some(MediaType::Unknown) | None => MediaType::from_specifier(specifier),
+        Some(media_type) => media_type,
     }
 }
@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use deno_core::anyhow;
+use deno_core::serde_json;
+use deno_core::serde_json::json;
+use deno_core::serde_json::Value;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::LoaderError;
+
+/// Records a SHA-256 checksum for every remote module, in the `remote`
+/// section of a `deno.lock` compatible file.
+///
+/// Other sections of an existing lockfile are kept as they are when the file
+/// is written back.
+pub struct Lockfile {
+    path: PathBuf,
+    write: bool,
+    content: Value,
+    remote: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// Reads the lockfile at `path`, starting from an empty one if it does not
+    /// exist yet. With `write` set, remote modules missing from the lockfile
+    /// are added to it; otherwise only the modules it already lists are checked.
+    pub fn new(path: impl Into<PathBuf>, write: bool) -> Result<Self, anyhow::Error> {
+        let path = path.into();
+        let content = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => json!({ "version": "2" }),
+            Err(e) => return Err(e.into()),
+        };
+        if !content.is_object() {
+            bail!("Lockfile is not a JSON object: {}", path.display());
+        }
+        let remote = match content.get("remote") {
+            Some(remote) => serde_json::from_value(remote.clone())?,
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            path,
+            write,
+            content,
+            remote,
+        })
+    }
+
+    /// Fails if the checksum of `source` differs from the one recorded for
+    /// `specifier`. Unknown specifiers are recorded when in write mode.
+    pub fn check_or_insert(&mut self, specifier: &str, source: &[u8]) -> Result<(), anyhow::Error> {
+        let checksum = format!("{:x}", Sha256::digest(source));
+        match self.remote.get(specifier) {
+            Some(expected) if *expected == checksum => Ok(()),
+            Some(expected) => Err(LoaderError::IntegrityMismatch {
+                specifier: specifier.to_string(),
+                expected: expected.clone(),
+                actual: checksum,
+            }
+            .into()),
+            None if self.write => {
+                self.remote.insert(specifier.to_string(), checksum);
+                self.save()
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn save(&mut self) -> Result<(), anyhow::Error> {
+        self.content["remote"] = serde_json::to_value(&self.remote)?;
+        let mut text = serde_json::to_string_pretty(&self.content)?;
+        text.push('\n');
+        std::fs::write(&self.path, text)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use deno_core::anyhow;
+use deno_core::error::generic_error;
+use deno_core::ModuleSpecifier;
+
+use crate::package_json;
+
+/// Resolves a bare specifier (`lodash/fp`) or a package import (`#utils`)
+/// imported by a local module the way Node does, through the `node_modules`
+/// directories and the package.json files above the referrer.
+///
+/// Returns `None` when no package provides the specifier, so the caller can
+/// report its own error.
+pub fn resolve(specifier: &str, referrer: &str) -> Result<Option<ModuleSpecifier>, anyhow::Error> {
+    let Some(referrer_path) = ModuleSpecifier::parse(referrer)
+        .ok()
+        .and_then(|referrer| referrer.to_file_path().ok())
+    else {
+        return Ok(None);
+    };
+    let Some(path) = resolve_path(specifier, &referrer_path)? else {
+        return Ok(None);
+    };
+    let specifier = ModuleSpecifier::from_file_path(&path)
+        .map_err(|_| generic_error(format!("Invalid module path: {}", path.display())))?;
+    Ok(Some(specifier))
+}
+
+fn resolve_path(specifier: &str, referrer: &Path) -> Result<Option<PathBuf>, anyhow::Error> {
+    if specifier.starts_with('#') {
+        return resolve_package_import(specifier, referrer);
+    }
+    let Some((name, sub_path)) = package_json::split_bare_specifier(specifier) else {
+        return Ok(None);
+    };
+    for dir in referrer.ancestors().skip(1) {
+        if dir.file_name().is_some_and(|name| name == "node_modules") {
+            continue;
+        }
+        let package_dir = dir.join("node_modules").join(name);
+        if package_dir.join("package.json").is_file() {
+            let package_json = package_json::read(&package_dir)?;
+            return package_json::resolve_subpath(&package_dir, &package_json, &sub_path).map(Some);
+        }
+        if package_dir.is_dir() {
+            return Ok(package_json::probe_file(&package_dir.join(&sub_path)));
+        }
+    }
+    Ok(None)
+}
+
+fn resolve_package_import(
+    specifier: &str,
+    referrer: &Path,
+) -> Result<Option<PathBuf>, anyhow::Error> {
+    let Some(package_dir) = referrer
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join("package.json").is_file())
+    else {
+        return Ok(None);
+    };
+    let package_json = package_json::read(package_dir)?;
+    let Some(target) = package_json::resolve_imports(&package_json, specifier) else {
+        bail!(
+            "Package import specifier \"{specifier}\" is not defined in {}",
+            package_dir.join("package.json").display()
+        );
+    };
+    if target.starts_with("./") {
+        return Ok(Some(package_dir.join(target)));
+    }
+    resolve_path(&target, &package_dir.join("package.json"))
+}
@@ -0,0 +1,356 @@
+use std::io::ErrorKind;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use base64::Engine;
+use deno_core::anyhow;
+use deno_core::error::generic_error;
+use deno_core::serde_json;
+use deno_core::serde_json::Value;
+use deno_core::ModuleSpecifier;
+use semver::Version;
+use sha2::Digest;
+use sha2::Sha512;
+
+use crate::package_json;
+use crate::package_req::parse_version_req;
+use crate::package_req::PackageReq;
+use crate::LoaderError;
+use crate::TypescriptModuleLoader;
+
+/// The npm registry packages are downloaded from, and where they are
+/// unpacked: `<cache_dir>/<registry host>/<name>/<version>`.
+#[derive(Clone, Debug)]
+pub struct NpmRegistry {
+    url: ModuleSpecifier,
+    cache_dir: PathBuf,
+}
+
+impl NpmRegistry {
+    pub fn new(mut url: ModuleSpecifier, cache_dir: PathBuf) -> Self {
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Self { url, cache_dir }
+    }
+
+    pub fn set_url(&mut self, url: ModuleSpecifier) {
+        *self = Self::new(url, std::mem::take(&mut self.cache_dir));
+    }
+
+    pub fn url(&self) -> &ModuleSpecifier {
+        &self.url
+    }
+
+    /// The path of `entry` in the directory of package `name`, making sure it
+    /// stays inside [`Self::root`] whatever the registry returned.
+    pub fn package_path(&self, name: &str, entry: &str) -> Result<PathBuf, anyhow::Error> {
+        let relative = Path::new(name).join(entry);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("Invalid npm package path: {}", relative.display());
+        }
+        Ok(self.root().join(relative))
+    }
+
+    pub fn root(&self) -> PathBuf {
+        let host = match (self.url.host_str(), self.url.port()) {
+            (Some(host), Some(port)) => format!("{host}_PORT{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => "_".to_string(),
+        };
+        self.cache_dir.join(host)
+    }
+}
+
+impl TypescriptModuleLoader {
+    /// Downloads the package an `npm:` specifier refers to and returns the
+    /// `file:` specifier of the module it points at.
+    pub(crate) async fn resolve_npm_specifier(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> Result<ModuleSpecifier, anyhow::Error> {
+        let req = PackageReq::parse(specifier)?;
+        let version_req = req.version_req.as_deref();
+        let packument = self.npm_packument(&req.name, version_req).await?;
+        let not_found = || LoaderError::NotFound {
+            specifier: specifier.clone(),
+        };
+        let version = select_version(&packument, version_req).ok_or_else(not_found)?;
+        let package_dir = self
+            .ensure_npm_package(&req.name, &version, &packument["versions"][&version])
+            .await?;
+        let package_json = package_json::read(&package_dir)?;
+        // A subpath the package does not export, or a file it does not have.
+        let path = package_json::resolve_subpath(&package_dir, &package_json, &req.sub_path)
+            .map_err(|_| not_found())?;
+        ModuleSpecifier::from_file_path(&path)
+            .map_err(|_| generic_error(format!("Invalid npm module path: {}", path.display())))
+    }
+
+    /// Maps a bare specifier imported by a module of a downloaded npm package
+    /// to an `npm:` specifier, using the version range its package.json
+    /// declares for that dependency.
+    pub(crate) fn resolve_npm_dependency(
+        &self,
+        specifier: &str,
+        referrer: &str,
+    ) -> Option<ModuleSpecifier> {
+        let referrer_path = ModuleSpecifier::parse(referrer).ok()?.to_file_path().ok()?;
+        if !referrer_path.starts_with(self.npm_registry.root()) {
+            return None;
+        }
+        let (name, sub_path) = package_json::split_bare_specifier(specifier)?;
+        // Nested package.json files (e.g. `dist/esm/package.json`) usually
+        // only set `type`, so look for the one that names the package.
+        let package_json = referrer_path
+            .ancestors()
+            .skip(1)
+            .filter(|dir| dir.join("package.json").is_file())
+            .filter_map(|dir| package_json::read(dir).ok())
+            .find(|package_json| package_json.get("name").is_some())?;
+        let version_req = ["dependencies", "peerDependencies", "optionalDependencies"]
+            .iter()
+            .find_map(|field| package_json[field][name].as_str())
+            .unwrap_or("*");
+        let sub_path = sub_path.trim_start_matches('.');
+        ModuleSpecifier::parse(&format!("npm:{name}@{version_req}{sub_path}")).ok()
+    }
+
+    /// Reads the registry metadata of a package, from the cache when it
+    /// already has a matching version.
+    async fn npm_packument(
+        &self,
+        name: &str,
+        version_req: Option<&str>,
+    ) -> Result<Value, anyhow::Error> {
+        let path = self.npm_registry.package_path(name, "registry.json")?;
+        match tokio::fs::read(&path).await {
+            Ok(cached) => {
+                let cached: Value = serde_json::from_slice(&cached)?;
+                if self.cached_only || select_version(&cached, version_req).is_some() {
+                    return Ok(cached);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        if self.cached_only {
+            return Err(LoaderError::NotCached {
+                specifier: format!("npm:{name}"),
+            }
+            .into());
+        }
+        let url = self.npm_registry.url().join(&name.replace('/', "%2f"))?;
+        let body = self.fetch_following_redirects(&url).await?.bytes().await?;
+        let packument = serde_json::from_slice(&body)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, &body).await?;
+        Ok(packument)
+    }
+
+    async fn ensure_npm_package(
+        &self,
+        name: &str,
+        version: &str,
+        version_info: &Value,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let package_dir = self.npm_registry.package_path(name, version)?;
+        if package_dir.join(".initialized").exists() {
+            return Ok(package_dir);
+        }
+        if self.cached_only {
+            return Err(LoaderError::NotCached {
+                specifier: format!("npm:{name}@{version}"),
+            }
+            .into());
+        }
+        // Loads of the same package, e.g. while prefetching a graph, wait for
+        // the first one to install it instead of replacing its files.
+        let install_lock = self
+            .npm_installs
+            .lock()
+            .unwrap()
+            .entry(format!("{name}@{version}"))
+            .or_default()
+            .clone();
+        let _guard = install_lock.lock().await;
+        if package_dir.join(".initialized").exists() {
+            return Ok(package_dir);
+        }
+        let dist = &version_info["dist"];
+        let tarball_url = dist["tarball"]
+            .as_str()
+            .ok_or_else(|| generic_error(format!("No tarball for npm package {name}@{version}")))?;
+        let tarball_url = ModuleSpecifier::parse(tarball_url)?;
+        let tarball = self
+            .fetch_following_redirects(&tarball_url)
+            .await?
+            .bytes()
+            .await?;
+        if let Some(integrity) = dist["integrity"].as_str() {
+            verify_integrity(&tarball, integrity, name, version)?;
+        }
+        let temp_dir = self
+            .npm_registry
+            .package_path(name, &format!(".{version}.tmp"))?;
+        let installed_dir = package_dir.clone();
+        tokio::task::spawn_blocking(move || install(&tarball, &temp_dir, &installed_dir)).await??;
+        Ok(package_dir)
+    }
+}
+
+/// Picks the version to use for `version_req`, which is a dist-tag or a
+/// version range. The `latest` tag is preferred when it is in range.
+fn select_version(packument: &Value, version_req: Option<&str>) -> Option<String> {
+    let dist_tags = &packument["dist-tags"];
+    if let Some(version) = dist_tags[version_req.unwrap_or("latest")].as_str() {
+        // Normalized, since the version ends up in a path.
+        return Some(Version::parse(version).ok()?.to_string());
+    }
+    let ranges = parse_version_req(version_req.unwrap_or("*"))?;
+    let matches = |version: &Version| ranges.iter().any(|range| range.matches(version));
+    if let Some(latest) = dist_tags["latest"]
+        .as_str()
+        .and_then(|v| Version::parse(v).ok())
+    {
+        if matches(&latest) {
+            return Some(latest.to_string());
+        }
+    }
+    packument["versions"]
+        .as_object()?
+        .keys()
+        .filter_map(|version| Version::parse(version).ok())
+        .filter(matches)
+        .max()
+        .map(|version| version.to_string())
+}
+
+fn verify_integrity(
+    tarball: &[u8],
+    integrity: &str,
+    name: &str,
+    version: &str,
+) -> Result<(), anyhow::Error> {
+    // Older packages only have a sha1 `shasum`, which is not checked.
+    let Some(expected) = integrity.strip_prefix("sha512-") else {
+        return Ok(());
+    };
+    let actual = base64::engine::general_purpose::STANDARD.encode(Sha512::digest(tarball));
+    if actual != expected {
+        return Err(LoaderError::IntegrityMismatch {
+            specifier: format!("npm:{name}@{version}"),
+            expected: format!("sha512-{expected}"),
+            actual: format!("sha512-{actual}"),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Unpacks a package next to its final location and moves it into place, so
+/// an interrupted download is never mistaken for a complete package.
+fn install(tarball: &[u8], temp_dir: &Path, package_dir: &Path) -> Result<(), anyhow::Error> {
+    if temp_dir.exists() {
+        std::fs::remove_dir_all(temp_dir)?;
+    }
+    unpack_tarball(tarball, temp_dir)?;
+    std::fs::write(temp_dir.join(".initialized"), "")?;
+    // What an interrupted install left behind.
+    if package_dir.exists() {
+        std::fs::remove_dir_all(package_dir)?;
+    }
+    std::fs::rename(temp_dir, package_dir)?;
+    Ok(())
+}
+
+fn unpack_tarball(tarball: &[u8], dest: &Path) -> Result<(), anyhow::Error> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+    std::fs::create_dir_all(dest)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+        let entry_path = entry.path()?.into_owned();
+        // Everything is under a top-level directory, usually `package/`.
+        let mut relative = PathBuf::new();
+        for component in entry_path.components().skip(1) {
+            match component {
+                Component::Normal(component) => relative.push(component),
+                _ => bail!("Invalid path in npm tarball: {}", entry_path.display()),
+            }
+        }
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        let path = dest.join(relative);
+        if entry_type.is_dir() {
+            std::fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            entry.unpack(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use deno_core::serde_json::json;
+
+    use super::*;
+
+    fn packument() -> Value {
+        json!({
+            "dist-tags": { "latest": "1.2.0", "next": "2.0.0-beta.1", "evil": "../../x" },
+            "versions": {
+                "1.0.0": {},
+                "1.2.0": {},
+                "1.3.0": {},
+                "2.0.0-beta.1": {},
+            },
+        })
+    }
+
+    #[test]
+    fn selects_versions() {
+        let packument = packument();
+        let select = |req| select_version(&packument, req);
+        assert_eq!(select(None).as_deref(), Some("1.2.0"));
+        assert_eq!(select(Some("latest")).as_deref(), Some("1.2.0"));
+        assert_eq!(select(Some("next")).as_deref(), Some("2.0.0-beta.1"));
+        // `latest` is preferred over newer versions in range.
+        assert_eq!(select(Some("^1.0.0")).as_deref(), Some("1.2.0"));
+        assert_eq!(select(Some("~1.3.0")).as_deref(), Some("1.3.0"));
+        assert_eq!(select(Some("1.0.0")).as_deref(), Some("1.0.0"));
+        assert_eq!(select(Some("^3.0.0")), None);
+        assert_eq!(select(Some("evil")), None);
+    }
+
+    #[test]
+    fn keeps_package_paths_inside_the_cache() {
+        let registry = NpmRegistry::new(
+            ModuleSpecifier::parse("http://localhost:4873").unwrap(),
+            PathBuf::from("/cache"),
+        );
+        assert_eq!(registry.root(), Path::new("/cache/localhost_PORT4873"));
+        assert_eq!(
+            registry.package_path("@scope/pkg", "1.0.0").unwrap(),
+            Path::new("/cache/localhost_PORT4873/@scope/pkg/1.0.0")
+        );
+        assert!(registry.package_path("pkg", "../../x").is_err());
+        assert!(registry.package_path("../pkg", "1.0.0").is_err());
+        assert!(registry.package_path("pkg", "/etc").is_err());
+    }
+}
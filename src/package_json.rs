@@ -0,0 +1,277 @@
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use deno_core::anyhow;
+use deno_core::error::generic_error;
+use serde_json::Map;
+use serde_json::Value;
+
+/// Conditions matched in `exports` targets, in the order they are declared
+/// in the package.json, which is why this module uses `serde_json` with the
+/// `preserve_order` feature directly.
+const CONDITIONS: &[&str] = &["deno", "import", "default"];
+
+const EXTENSIONS: &[&str] = &["js", "mjs", "cjs", "json"];
+
+pub fn read(package_dir: &Path) -> Result<Value, anyhow::Error> {
+    let path = package_dir.join("package.json");
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| generic_error(format!("Failed to read {}: {e}", path.display())))?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Splits a bare specifier like `@scope/pkg/sub` into the package name and
+/// a subpath relative to the package (`.` or `./sub`).
+pub fn split_bare_specifier(specifier: &str) -> Option<(&str, String)> {
+    if specifier.is_empty()
+        || specifier.starts_with('.')
+        || specifier.starts_with('/')
+        || specifier.contains(':')
+    {
+        return None;
+    }
+    let name_end = if specifier.starts_with('@') {
+        let scope_end = specifier.find('/')?;
+        specifier[scope_end + 1..]
+            .find('/')
+            .map(|i| scope_end + 1 + i)
+            .unwrap_or(specifier.len())
+    } else {
+        specifier.find('/').unwrap_or(specifier.len())
+    };
+    let (name, rest) = specifier.split_at(name_end);
+    Some((name, format!(".{rest}")))
+}
+
+/// Resolves `sub_path` (`.` or `./sub`) of the package in `package_dir`
+/// through its `exports`, falling back to `module`, `main` and the file
+/// system when the package has no `exports`.
+pub fn resolve_subpath(
+    package_dir: &Path,
+    package_json: &Value,
+    sub_path: &str,
+) -> Result<PathBuf, anyhow::Error> {
+    if let Some(exports) = package_json.get("exports").filter(|e| !e.is_null()) {
+        let target = resolve_exports(exports, sub_path).ok_or_else(|| {
+            generic_error(format!(
+                "Package subpath '{sub_path}' is not defined by \"exports\" in {}",
+                package_dir.join("package.json").display()
+            ))
+        })?;
+        return join_inside(package_dir, &target);
+    }
+    let path = if sub_path == "." {
+        let main = ["module", "main"]
+            .iter()
+            .find_map(|field| package_json[field].as_str());
+        join_inside(package_dir, main.unwrap_or("index"))?
+    } else {
+        join_inside(package_dir, sub_path)?
+    };
+    probe_file(&path).ok_or_else(|| {
+        generic_error(format!(
+            "Cannot find module '{}' in package {}",
+            path.display(),
+            package_dir.display()
+        ))
+    })
+}
+
+/// Joins a path taken from a specifier or package.json to the package
+/// directory, failing when it would point outside of it.
+fn join_inside(package_dir: &Path, relative: &str) -> Result<PathBuf, anyhow::Error> {
+    let escapes = Path::new(relative)
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    if escapes {
+        return Err(generic_error(format!(
+            "Path '{relative}' is outside of package {}",
+            package_dir.display()
+        )));
+    }
+    Ok(package_dir.join(relative))
+}
+
+fn resolve_exports(exports: &Value, sub_path: &str) -> Option<String> {
+    match exports.as_object() {
+        Some(map) if map.keys().any(|key| key.starts_with('.')) => {
+            resolve_pattern_map(map, sub_path, false)
+        }
+        // A string, array or conditions object only exports the main entry.
+        _ if sub_path == "." => resolve_target(exports, None, false),
+        _ => None,
+    }
+}
+
+/// Resolves a `#` specifier through the `imports` of a package.json. The
+/// result is either relative to the package (`./lib/x.js`) or a bare
+/// specifier of another package.
+pub fn resolve_imports(package_json: &Value, specifier: &str) -> Option<String> {
+    resolve_pattern_map(package_json.get("imports")?.as_object()?, specifier, true)
+}
+
+/// Looks `key` up in an `exports` or `imports` map, where keys may contain a
+/// single `*` that is substituted into the target.
+fn resolve_pattern_map(map: &Map<String, Value>, key: &str, allow_bare: bool) -> Option<String> {
+    if !key.contains('*') {
+        if let Some(target) = map.get(key) {
+            return resolve_target(target, None, allow_bare);
+        }
+    }
+    let mut best_match: Option<(&str, &str)> = None;
+    for pattern in map.keys() {
+        let Some((prefix, suffix)) = pattern.split_once('*') else {
+            continue;
+        };
+        if key.len() < prefix.len() + suffix.len()
+            || !key.starts_with(prefix)
+            || !key.ends_with(suffix)
+        {
+            continue;
+        }
+        // The pattern with the longest prefix wins.
+        if best_match.map_or(true, |(best, _)| prefix.len() > best.find('*').unwrap_or(0)) {
+            best_match = Some((pattern, &key[prefix.len()..key.len() - suffix.len()]));
+        }
+    }
+    let (pattern, matched) = best_match?;
+    // What the `*` matched is substituted into the target, so it must not
+    // leave the package either.
+    if matched
+        .split(['/', '\\'])
+        .any(|s| s == ".." || s == "node_modules")
+    {
+        return None;
+    }
+    resolve_target(&map[pattern], Some(matched), allow_bare)
+}
+
+fn resolve_target(target: &Value, pattern_match: Option<&str>, allow_bare: bool) -> Option<String> {
+    match target {
+        Value::String(target) => {
+            let is_bare = split_bare_specifier(target).is_some();
+            // Targets must stay inside the package.
+            if !(target.starts_with("./") || (allow_bare && is_bare))
+                || target.split('/').any(|s| s == ".." || s == "node_modules")
+            {
+                return None;
+            }
+            Some(match pattern_match {
+                Some(matched) => target.replace('*', matched),
+                None => target.clone(),
+            })
+        }
+        Value::Array(targets) => targets
+            .iter()
+            .find_map(|target| resolve_target(target, pattern_match, allow_bare)),
+        Value::Object(conditions) => conditions
+            .iter()
+            .filter(|(condition, _)| CONDITIONS.contains(&condition.as_str()))
+            .find_map(|(_, target)| resolve_target(target, pattern_match, allow_bare)),
+        _ => None,
+    }
+}
+
+/// Finds the file a path without an extension, or a directory, refers to.
+pub fn probe_file(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    for extension in EXTENSIONS {
+        let mut candidate = path.as_os_str().to_owned();
+        candidate.push(".");
+        candidate.push(extension);
+        let candidate = PathBuf::from(candidate);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+    }
+    if path.is_dir() {
+        return probe_file(&path.join("index"));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn map(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn splits_bare_specifiers() {
+        assert_eq!(split_bare_specifier("pkg"), Some(("pkg", ".".to_string())));
+        assert_eq!(
+            split_bare_specifier("pkg/sub/path.js"),
+            Some(("pkg", "./sub/path.js".to_string()))
+        );
+        assert_eq!(
+            split_bare_specifier("@scope/pkg"),
+            Some(("@scope/pkg", ".".to_string()))
+        );
+        assert_eq!(
+            split_bare_specifier("@scope/pkg/sub"),
+            Some(("@scope/pkg", "./sub".to_string()))
+        );
+        for specifier in [
+            "",
+            "./pkg",
+            "../pkg",
+            "/pkg",
+            "node:fs",
+            "https://x/y",
+            "@scope",
+        ] {
+            assert_eq!(split_bare_specifier(specifier), None, "{specifier}");
+        }
+    }
+
+    #[test]
+    fn resolves_pattern_maps() {
+        let exports = map(json!({
+            ".": { "types": "./index.d.ts", "import": "./index.mjs", "require": "./index.cjs" },
+            "./feature": ["./missing/../feature.js", "./feature.js"],
+            "./lib/*": "./dist/lib/*.js",
+            "./lib/internal/*": null,
+            "./escape": "../outside.js",
+        }));
+        let resolve = |key| resolve_pattern_map(&exports, key, false);
+        assert_eq!(resolve(".").as_deref(), Some("./index.mjs"));
+        assert_eq!(resolve("./feature").as_deref(), Some("./feature.js"));
+        assert_eq!(resolve("./lib/a/b").as_deref(), Some("./dist/lib/a/b.js"));
+        // The pattern with the longest prefix wins.
+        assert_eq!(resolve("./lib/internal/x"), None);
+        assert_eq!(resolve("./escape"), None);
+        assert_eq!(resolve("./lib/../../x"), None);
+        assert_eq!(resolve("./missing"), None);
+
+        let imports = map(json!({
+            "#dep": "other-pkg",
+            "#utils/*": { "deno": "./src/utils/*.ts", "default": "./utils/*.js" },
+        }));
+        assert_eq!(
+            resolve_pattern_map(&imports, "#dep", true).as_deref(),
+            Some("other-pkg")
+        );
+        assert_eq!(resolve_pattern_map(&imports, "#dep", false), None);
+        assert_eq!(
+            resolve_pattern_map(&imports, "#utils/fs", true).as_deref(),
+            Some("./src/utils/fs.ts")
+        );
+    }
+
+    #[test]
+    fn keeps_subpaths_inside_the_package() {
+        let package_dir = Path::new("/pkg");
+        let package_json = json!({ "main": "../outside.js" });
+        assert!(resolve_subpath(package_dir, &package_json, ".").is_err());
+        assert!(resolve_subpath(package_dir, &json!({}), "./../../etc/passwd").is_err());
+        let package_json = json!({ "exports": { "./*": "./*" } });
+        assert!(resolve_subpath(package_dir, &package_json, "./../../etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use deno_core::anyhow;
+use deno_core::error::generic_error;
+use deno_core::ModuleSpecifier;
+use semver::VersionReq;
+
+/// A parsed `npm:` or `jsr:` specifier, `<name>[@<version>][/<sub path>]`.
+pub struct PackageReq {
+    pub name: String,
+    pub version_req: Option<String>,
+    /// `.` or `./<sub path>`.
+    pub sub_path: String,
+}
+
+impl PackageReq {
+    pub fn parse(specifier: &ModuleSpecifier) -> Result<Self, anyhow::Error> {
+        let invalid = || generic_error(format!("Invalid package specifier: {specifier}"));
+        let text = percent_encoding::percent_decode_str(specifier.path())
+            .decode_utf8()
+            .map_err(|_| invalid())?;
+        let text = text.trim_start_matches('/');
+        let (scope, rest) = match text.strip_prefix('@') {
+            Some(rest) => {
+                let (scope, rest) = rest.split_once('/').ok_or_else(invalid)?;
+                (Some(scope), rest)
+            }
+            None => (None, text),
+        };
+        let (name_and_version, sub_path) = match rest.split_once('/') {
+            Some((name_and_version, sub_path)) => (name_and_version, format!("./{sub_path}")),
+            None => (rest, ".".to_string()),
+        };
+        let (name, version_req) = match name_and_version.split_once('@') {
+            Some((name, version_req)) => (name, Some(version_req.to_string())),
+            None => (name_and_version, None),
+        };
+        if !is_valid_name_segment(name) || scope.is_some_and(|scope| !is_valid_name_segment(scope))
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            name: match scope {
+                Some(scope) => format!("@{scope}/{name}"),
+                None => name.to_string(),
+            },
+            version_req,
+            sub_path,
+        })
+    }
+}
+
+/// Whether `segment` is allowed as a package name or scope by npm's rules:
+/// URL-safe characters only and no leading dot, which also keeps names from
+/// escaping the directories they are unpacked to.
+fn is_valid_name_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && !segment.starts_with('.')
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'))
+}
+
+/// Converts an npm version range into the `semver` crate's syntax, which
+/// separates comparators with commas and treats bare versions as caret
+/// requirements.
+pub fn parse_version_req(text: &str) -> Option<Vec<VersionReq>> {
+    text.split("||")
+        .map(|range| {
+            let range = range.trim();
+            if range.is_empty() || range == "x" || range == "X" {
+                return Some(VersionReq::STAR);
+            }
+            if let Some((low, high)) = range.split_once(" - ") {
+                return VersionReq::parse(&format!(">={}, <={}", low.trim(), high.trim())).ok();
+            }
+            let comparators = range
+                .split_whitespace()
+                .map(|comparator| {
+                    let comparator = comparator.trim_start_matches('v');
+                    if !comparator.starts_with(|c: char| c.is_ascii_digit())
+                        || comparator.contains(['x', 'X', '*'])
+                    {
+                        return comparator.to_string();
+                    }
+                    let release = comparator.split(['-', '+']).next().unwrap_or_default();
+                    if release.split('.').count() == 3 {
+                        format!("={comparator}")
+                    } else {
+                        format!("~{comparator}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            VersionReq::parse(&comparators).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::*;
+
+    fn parse(specifier: &str) -> Result<PackageReq, anyhow::Error> {
+        PackageReq::parse(&ModuleSpecifier::parse(specifier).unwrap())
+    }
+
+    fn matches(req: &str, version: &str) -> bool {
+        let version = Version::parse(version).unwrap();
+        parse_version_req(req)
+            .unwrap()
+            .iter()
+            .any(|range| range.matches(&version))
+    }
+
+    #[test]
+    fn parses_package_specifiers() {
+        let req = parse("npm:preact").unwrap();
+        assert_eq!(req.name, "preact");
+        assert_eq!(req.version_req, None);
+        assert_eq!(req.sub_path, ".");
+
+        let req = parse("npm:preact@^10.0.0/hooks").unwrap();
+        assert_eq!(req.name, "preact");
+        assert_eq!(req.version_req.as_deref(), Some("^10.0.0"));
+        assert_eq!(req.sub_path, "./hooks");
+
+        let req = parse("jsr:@std/path@1/posix/join").unwrap();
+        assert_eq!(req.name, "@std/path");
+        assert_eq!(req.version_req.as_deref(), Some("1"));
+        assert_eq!(req.sub_path, "./posix/join");
+
+        let req = parse("npm:/@types/node@%3E=18").unwrap();
+        assert_eq!(req.name, "@types/node");
+        assert_eq!(req.version_req.as_deref(), Some(">=18"));
+    }
+
+    #[test]
+    fn rejects_names_that_escape_the_package_dir() {
+        for specifier in [
+            "npm:..",
+            "npm:.hidden",
+            "npm:@scope",
+            "npm:@../pkg",
+            "npm:@scope/..",
+            "npm:%2E%2E%2Fetc",
+            "npm:pkg%5Cother",
+            "npm:",
+        ] {
+            assert!(parse(specifier).is_err(), "{specifier}");
+        }
+    }
+
+    #[test]
+    fn parses_npm_version_ranges() {
+        assert!(matches("1.2.3", "1.2.3"));
+        assert!(!matches("1.2.3", "1.2.4"));
+        assert!(matches("1.2", "1.2.9"));
+        assert!(!matches("1.2", "1.3.0"));
+        assert!(matches("^1.2.0", "1.9.0"));
+        assert!(matches("~1.2.0", "1.2.5"));
+        assert!(!matches("~1.2.0", "1.3.0"));
+        assert!(matches("v2.0.0", "2.0.0"));
+        assert!(matches("1.x", "1.4.0"));
+        assert!(matches(">=1.0.0 <2.0.0", "1.5.0"));
+        assert!(!matches(">=1.0.0 <2.0.0", "2.0.0"));
+        assert!(matches("1.0.0 - 1.5.0", "1.5.0"));
+        assert!(!matches("1.0.0 - 1.5.0", "1.5.1"));
+        assert!(matches("^1.0.0 || ^3.0.0", "3.1.0"));
+        assert!(!matches("^1.0.0 || ^3.0.0", "2.0.0"));
+        assert!(matches("*", "4.0.0"));
+        assert!(matches("", "4.0.0"));
+        assert!(parse_version_req("not a range").is_none());
+    }
+}
@@ -0,0 +1,312 @@
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use deno_core::anyhow;
+use deno_core::error::generic_error;
+use deno_core::ModuleSpecifier;
+
+use crate::LoaderError;
+
+/// A rule matching remote module URLs: a host (`deno.land`), a host and port
+/// (`localhost:8000`) or a URL prefix (`https://deno.land/std@0.195.0/`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostRule {
+    Host(String),
+    HostPort(String, u16),
+    UrlPrefix(ModuleSpecifier),
+}
+
+impl FromStr for HostRule {
+    type Err = anyhow::Error;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        if rule.contains("://") {
+            return Ok(Self::UrlPrefix(ModuleSpecifier::parse(rule)?));
+        }
+        let invalid = || generic_error(format!("Invalid host rule: {rule}"));
+        // Parse through a URL so hosts are normalized the same way as the
+        // specifiers they are matched against.
+        let url = ModuleSpecifier::parse(&format!("http://{rule}/")).map_err(|_| invalid())?;
+        // Anything but a host and port ends up elsewhere in the URL.
+        if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+            return Err(invalid());
+        }
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(invalid());
+        }
+        let host = url.host_str().ok_or_else(invalid)?.to_string();
+        match rule.rsplit_once(':') {
+            Some((_, port)) if !rule.ends_with(']') => {
+                Ok(Self::HostPort(host, port.parse().map_err(|_| invalid())?))
+            }
+            _ => Ok(Self::Host(host)),
+        }
+    }
+}
+
+impl HostRule {
+    pub fn matches(&self, url: &ModuleSpecifier) -> bool {
+        match self {
+            Self::Host(host) => host_of(url) == Some(trim_root(host)),
+            Self::HostPort(host, port) => {
+                host_of(url) == Some(trim_root(host)) && url.port_or_known_default() == Some(*port)
+            }
+            Self::UrlPrefix(prefix) => {
+                if url.scheme() != prefix.scheme()
+                    || host_of(url) != host_of(prefix)
+                    || url.port_or_known_default() != prefix.port_or_known_default()
+                {
+                    return false;
+                }
+                // Only match whole path segments, so that `/std` does not
+                // match `/stdlib`.
+                let prefix_path = prefix.path().trim_end_matches('/');
+                match url.path().strip_prefix(prefix_path) {
+                    Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+/// The host of `url` without the trailing dot of a fully qualified name, so
+/// that `evil.com.` matches rules for `evil.com`.
+fn host_of(url: &ModuleSpecifier) -> Option<&str> {
+    url.host_str().map(trim_root)
+}
+
+fn trim_root(host: &str) -> &str {
+    host.strip_suffix('.').unwrap_or(host)
+}
+
+/// Restricts what the loader may load. The default allows everything except
+/// remote modules importing local files.
+#[derive(Clone, Debug, Default)]
+pub struct Permissions {
+    /// When set, only remote modules matching one of these rules are fetched.
+    pub allow_net: Option<Vec<HostRule>>,
+    /// Remote modules matching any of these rules are never fetched, even if
+    /// they are also allowed.
+    pub deny_net: Vec<HostRule>,
+    /// When set, only files inside one of these directories are read. Both
+    /// the directories and the files are canonicalized first, so symlinks
+    /// cannot be used to escape them. Files of npm packages downloaded by
+    /// the loader can always be read.
+    pub allow_read: Option<Vec<PathBuf>>,
+    /// Lets modules served over `http`/`https` import `file:` modules, which
+    /// is rejected by default as in Deno.
+    pub allow_remote_to_local_imports: bool,
+}
+
+impl Permissions {
+    pub fn check_net(&self, url: &ModuleSpecifier) -> Result<(), anyhow::Error> {
+        let denied = self.deny_net.iter().any(|rule| rule.matches(url))
+            || self
+                .allow_net
+                .as_ref()
+                .is_some_and(|allow| !allow.iter().any(|rule| rule.matches(url)));
+        if denied {
+            return Err(LoaderError::PermissionDenied {
+                message: format!("Requires net access to \"{url}\""),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Rejects imports of `file:` modules by remote modules. A referrer is
+    /// remote when it was served over `http`/`https`, or when a remote module
+    /// created it, like a `data:` URL it imports.
+    pub fn check_import(
+        &self,
+        specifier: &ModuleSpecifier,
+        referrer: &str,
+        referrer_is_remote: bool,
+    ) -> Result<(), anyhow::Error> {
+        if self.allow_remote_to_local_imports || specifier.scheme() != "file" {
+            return Ok(());
+        }
+        if referrer_is_remote {
+            return Err(LoaderError::PermissionDenied {
+                message: format!(
+                    "Remote modules are not allowed to import local modules.\n  Importing: {specifier}\n    at {referrer}"
+                ),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Returns the path to read `path` from, which is canonicalized when read
+    /// access is restricted.
+    ///
+    /// Paths outside of the allowed directories are denied whether or not
+    /// they exist, so that their existence cannot be probed. Only a missing
+    /// path inside them fails with the I/O error.
+    pub fn check_read(&self, path: &Path) -> Result<PathBuf, anyhow::Error> {
+        let Some(allow_read) = &self.allow_read else {
+            return Ok(path.to_path_buf());
+        };
+        let roots: Vec<PathBuf> = allow_read
+            .iter()
+            .flat_map(|root| [std::fs::canonicalize(root).ok(), Some(normalize(root))])
+            .flatten()
+            .collect();
+        let is_allowed = |path: &Path| roots.iter().any(|root| path.starts_with(root));
+        match std::fs::canonicalize(path) {
+            Ok(canonical) if is_allowed(&canonical) => return Ok(canonical),
+            Ok(_) => {}
+            Err(e) if is_allowed(&normalize(path)) => return Err(e.into()),
+            Err(_) => {}
+        }
+        Err(LoaderError::PermissionDenied {
+            message: format!("Requires read access to \"{}\"", path.display()),
+        }
+        .into())
+    }
+}
+
+/// Resolves the `.` and `..` components of `path` without touching the file
+/// system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> ModuleSpecifier {
+        ModuleSpecifier::parse(url).unwrap()
+    }
+
+    #[test]
+    fn parses_host_rules() {
+        assert_eq!(
+            "Deno.Land".parse::<HostRule>().unwrap(),
+            HostRule::Host("deno.land".to_string())
+        );
+        assert_eq!(
+            "localhost:8000".parse::<HostRule>().unwrap(),
+            HostRule::HostPort("localhost".to_string(), 8000)
+        );
+        assert_eq!(
+            "[::1]".parse::<HostRule>().unwrap(),
+            HostRule::Host("[::1]".to_string())
+        );
+        assert_eq!(
+            "[::1]:8000".parse::<HostRule>().unwrap(),
+            HostRule::HostPort("[::1]".to_string(), 8000)
+        );
+        assert_eq!(
+            "https://deno.land/std/".parse::<HostRule>().unwrap(),
+            HostRule::UrlPrefix(url("https://deno.land/std/"))
+        );
+        for rule in [
+            "",
+            "localhost:port",
+            "deno.land/std",
+            "user@deno.land",
+            "a b",
+        ] {
+            assert!(rule.parse::<HostRule>().is_err(), "{rule}");
+        }
+    }
+
+    #[test]
+    fn matches_urls() {
+        let host: HostRule = "deno.land".parse().unwrap();
+        assert!(host.matches(&url("https://deno.land/x/mod.ts")));
+        assert!(host.matches(&url("http://deno.land:8080/x/mod.ts")));
+        assert!(!host.matches(&url("https://sub.deno.land/x/mod.ts")));
+        assert!(host.matches(&url("https://deno.land./x/mod.ts")));
+        let fully_qualified: HostRule = "deno.land.".parse().unwrap();
+        assert!(fully_qualified.matches(&url("https://deno.land/x/mod.ts")));
+
+        let host_port: HostRule = "localhost:443".parse().unwrap();
+        assert!(host_port.matches(&url("https://localhost/mod.ts")));
+        assert!(!host_port.matches(&url("http://localhost/mod.ts")));
+        assert!(host_port.matches(&url("https://localhost./mod.ts")));
+
+        let prefix: HostRule = "https://deno.land/std".parse().unwrap();
+        assert!(prefix.matches(&url("https://deno.land/std")));
+        assert!(prefix.matches(&url("https://deno.land/std/path/mod.ts")));
+        assert!(!prefix.matches(&url("https://deno.land/stdlib/mod.ts")));
+        assert!(prefix.matches(&url("https://deno.land./std/path/mod.ts")));
+        assert!(!prefix.matches(&url("http://deno.land/std/path/mod.ts")));
+        assert!(!prefix.matches(&url("https://deno.land:8443/std/path/mod.ts")));
+    }
+
+    fn is_denied(result: Result<PathBuf, anyhow::Error>) -> bool {
+        matches!(
+            result.unwrap_err().downcast_ref::<LoaderError>(),
+            Some(LoaderError::PermissionDenied { .. })
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restricts_reads_to_allowed_directories() {
+        let dir = std::env::temp_dir().join(format!(
+            "basic_deno_ts_module_loader-read-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (root, outside) = (dir.join("root"), dir.join("outside"));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("inside.js"), "").unwrap();
+        std::fs::write(outside.join("secret.js"), "").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.js"), root.join("link.js")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("dir_link")).unwrap();
+
+        let permissions = Permissions {
+            allow_read: Some(vec![root.clone()]),
+            ..Default::default()
+        };
+        let inside = permissions.check_read(&root.join("inside.js")).unwrap();
+        assert_eq!(
+            inside,
+            std::fs::canonicalize(root.join("inside.js")).unwrap()
+        );
+
+        // Traversal and symlinks cannot escape the root.
+        assert!(is_denied(
+            permissions.check_read(&root.join("../outside/secret.js"))
+        ));
+        assert!(is_denied(permissions.check_read(&root.join("link.js"))));
+        assert!(is_denied(
+            permissions.check_read(&root.join("dir_link/secret.js"))
+        ));
+
+        // Missing paths outside the root look the same as existing ones.
+        assert!(is_denied(
+            permissions.check_read(&outside.join("missing.js"))
+        ));
+        assert!(is_denied(
+            permissions.check_read(&root.join("../outside/missing.js"))
+        ));
+        let missing = permissions
+            .check_read(&root.join("missing.js"))
+            .unwrap_err();
+        assert!(missing.downcast_ref::<std::io::Error>().is_some());
+
+        let permissions = Permissions::default();
+        assert!(permissions.check_read(&outside.join("missing.js")).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use deno_ast::dep_graph::DependencyKind;
+use deno_ast::MediaType;
+use deno_ast::ParseParams;
+use deno_ast::SourceTextInfo;
+use deno_core::futures::stream::FuturesUnordered;
+use deno_core::futures::StreamExt;
+use deno_core::ModuleLoader;
+use deno_core::ModuleSpecifier;
+use deno_core::ModuleType;
+use deno_core::ResolutionKind;
+
+use crate::LoadedModule;
+use crate::TypescriptModuleLoader;
+
+/// How many modules are loaded at once while prefetching a module graph.
+const MAX_CONCURRENT_LOADS: usize = 8;
+
+/// Modules loaded by `prepare_load`, waiting for deno_core to ask for them.
+#[derive(Default)]
+pub(crate) struct Prefetched {
+    /// The modules prefetched for each graph root. deno_core calls
+    /// `prepare_load` for the main module and for every dynamic import, and
+    /// each call only replaces what an earlier call for the same root left
+    /// over.
+    graphs: HashMap<ModuleSpecifier, HashMap<ModuleSpecifier, LoadedModule>>,
+    /// Modules deno_core has loaded already, which it will not ask for again.
+    taken: HashSet<ModuleSpecifier>,
+}
+
+impl Prefetched {
+    pub fn take(&mut self, specifier: &ModuleSpecifier) -> Option<LoadedModule> {
+        self.taken.insert(specifier.clone());
+        let mut taken = None;
+        for modules in self.graphs.values_mut() {
+            if let Some(module) = modules.remove(specifier) {
+                taken.get_or_insert(module);
+            }
+        }
+        self.graphs.retain(|_, modules| !modules.is_empty());
+        taken
+    }
+
+    /// Drops a prefetched module whose source changed, so that `load` loads
+    /// it again.
+    pub fn discard(&mut self, specifier: &ModuleSpecifier) {
+        for modules in self.graphs.values_mut() {
+            modules.remove(specifier);
+        }
+    }
+
+    /// Whether `specifier` needs no prefetching: deno_core has it already,
+    /// or it is waiting for deno_core in the graph of another root.
+    fn contains(&self, specifier: &ModuleSpecifier) -> bool {
+        self.taken.contains(specifier)
+            || self
+                .graphs
+                .values()
+                .any(|modules| modules.contains_key(specifier))
+    }
+}
+
+impl TypescriptModuleLoader {
+    /// Loads the graph of static imports rooted at `root`, several modules at
+    /// a time, and keeps the modules for the `load` calls that follow.
+    ///
+    /// Modules deno_core already has are skipped along with their imports.
+    /// Modules that fail to load are left out, so that `load` reports the
+    /// error when deno_core gets to them. Modules an earlier prefetch of the
+    /// same root left over, e.g. because loading that graph failed part-way,
+    /// are dropped so they cannot be served stale later.
+    pub(crate) async fn prefetch_graph(&self, root: ModuleSpecifier) {
+        self.prefetched.lock().unwrap().graphs.remove(&root);
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([root.clone()]);
+        let mut loading = FuturesUnordered::new();
+        loop {
+            while loading.len() < MAX_CONCURRENT_LOADS {
+                let Some(specifier) = queue.pop_front() else {
+                    break;
+                };
+                if !seen.insert(specifier.clone())
+                    || self.prefetched.lock().unwrap().contains(&specifier)
+                {
+                    continue;
+                }
+                loading.push(async move {
+                    let result = self.load_module(&specifier).await;
+                    (specifier, result)
+                });
+            }
+            let Some((specifier, result)) = loading.next().await else {
+                break;
+            };
+            let Ok(module) = result else {
+                continue;
+            };
+            queue.extend(self.static_imports(&module));
+            self.prefetched
+                .lock()
+                .unwrap()
+                .graphs
+                .entry(root.clone())
+                .or_default()
+                .insert(specifier, module);
+        }
+    }
+
+    /// The modules a loaded module imports statically, resolved the way
+    /// deno_core will resolve them. Imports that fail to resolve are left
+    /// for deno_core to report.
+    fn static_imports(&self, module: &LoadedModule) -> Vec<ModuleSpecifier> {
+        if module.module_type != ModuleType::JavaScript {
+            return Vec::new();
+        }
+        // The code is plain JavaScript at this point, even for TypeScript or
+        // CommonJS modules.
+        let Ok(parsed) = deno_ast::parse_module(ParseParams {
+            specifier: module.specifier.to_string(),
+            text_info: SourceTextInfo::from_string(module.code.to_string()),
+            media_type: MediaType::JavaScript,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        }) else {
+            return Vec::new();
+        };
+        parsed
+            .analyze_dependencies()
+            .into_iter()
+            .filter(|dependency| {
+                !dependency.is_dynamic
+                    && matches!(
+                        dependency.kind,
+                        DependencyKind::Import | DependencyKind::Export
+                    )
+            })
+            .filter_map(|dependency| {
+                self.resolve(
+                    &dependency.specifier,
+                    module.specifier.as_str(),
+                    ResolutionKind::Import,
+                )
+                .ok()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use deno_core::anyhow;
+    use deno_core::futures::future::LocalBoxFuture;
+    use deno_core::futures::FutureExt;
+
+    use super::*;
+    use crate::FetchedModule;
+    use crate::SchemeHandler;
+
+    /// Serves `test:` modules from memory, recording the path of each one it
+    /// fetches.
+    struct TestHandler {
+        fetched: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SchemeHandler for TestHandler {
+        fn fetch<'a>(
+            &'a self,
+            specifier: &'a ModuleSpecifier,
+        ) -> LocalBoxFuture<'a, Result<FetchedModule, anyhow::Error>> {
+            self.fetched
+                .lock()
+                .unwrap()
+                .push(specifier.path().to_string());
+            let code = match specifier.path() {
+                "/main.js" => "import './a.js';\nawait import('./lazy.js');\n",
+                "/lazy.js" => "import './a.js';\nimport './b.js';\n",
+                _ => "export {};\n",
+            };
+            let module = FetchedModule {
+                specifier: specifier.clone(),
+                bytes: code.as_bytes().to_vec(),
+                media_type: MediaType::JavaScript,
+            };
+            async move { Ok(module) }.boxed_local()
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_modules_that_were_already_taken() {
+        let fetched = Arc::new(Mutex::new(Vec::new()));
+        let loader = TypescriptModuleLoader::new(
+            reqwest::Client::new(),
+            std::env::temp_dir().join("basic_deno_ts_module_loader-prefetch"),
+        )
+        .with_scheme_handler(
+            "test",
+            TestHandler {
+                fetched: fetched.clone(),
+            },
+        );
+        let specifier = |path: &str| ModuleSpecifier::parse(&format!("test://{path}")).unwrap();
+
+        loader
+            .prepare_load(&specifier("/main.js"), None, false)
+            .await
+            .unwrap();
+        for path in ["/main.js", "/a.js"] {
+            loader.load(&specifier(path), None, false).await.unwrap();
+        }
+        assert_eq!(*fetched.lock().unwrap(), ["/main.js", "/a.js"]);
+
+        // The dynamic import only prefetches what deno_core does not have.
+        loader
+            .prepare_load(&specifier("/lazy.js"), None, true)
+            .await
+            .unwrap();
+        assert_eq!(fetched.lock().unwrap()[2..], ["/lazy.js", "/b.js"]);
+        for path in ["/lazy.js", "/b.js"] {
+            loader.load(&specifier(path), None, true).await.unwrap();
+        }
+        assert_eq!(fetched.lock().unwrap().len(), 4);
+        assert!(loader.prefetched.lock().unwrap().graphs.is_empty());
+    }
+}
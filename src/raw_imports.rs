@@ -0,0 +1,118 @@
+use base64::Engine;
+use deno_core::anyhow;
+use deno_core::ModuleSpecifier;
+
+use crate::cjs::js_string;
+use crate::LoaderError;
+
+/// How files mapped with [`crate::TypescriptModuleLoader::with_raw_import`]
+/// are served: as a module whose default export is their content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawImportType {
+    /// The content as a string; the file must be valid UTF-8.
+    Text,
+    /// The content as a `Uint8Array`.
+    Bytes,
+}
+
+/// The extension of the last path segment of `specifier`, if any.
+pub fn extension(specifier: &ModuleSpecifier) -> Option<&str> {
+    let file_name = specifier.path().rsplit('/').next()?;
+    let (_, extension) = file_name.rsplit_once('.')?;
+    Some(extension)
+}
+
+pub fn wrap(
+    bytes: Vec<u8>,
+    import_type: RawImportType,
+    specifier: &ModuleSpecifier,
+) -> Result<String, anyhow::Error> {
+    let value = match import_type {
+        RawImportType::Text => {
+            let text = String::from_utf8(bytes).map_err(|_| LoaderError::InvalidContent {
+                specifier: specifier.clone(),
+                message: "not valid UTF-8".to_string(),
+            })?;
+            js_string(&text)
+        }
+        RawImportType::Bytes => js_bytes(&bytes),
+    };
+    Ok(format!("export default {value};\n"))
+}
+
+/// A JS expression evaluating to a `Uint8Array` of `bytes`. They are embedded
+/// as base64 and decoded by hand since the runtime may not provide `atob`.
+pub fn js_bytes(bytes: &[u8]) -> String {
+    format!(
+        "((base64) => {{\n\
+         const bytes = new Uint8Array(Math.floor(base64.length * 3 / 4));\n\
+         const alphabet = \"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/\";\n\
+         let value = 0, bits = 0, j = 0;\n\
+         for (let i = 0; i < base64.length; i++) {{\n\
+         value = ((value << 6) | alphabet.indexOf(base64[i])) & 0xffff;\n\
+         bits += 6;\n\
+         if (bits >= 8) {{\n\
+         bits -= 8;\n\
+         bytes[j++] = (value >> bits) & 0xff;\n\
+         }}\n\
+         }}\n\
+         return bytes;\n\
+         }})(\"{}\")",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(bytes)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use deno_core::serde_v8;
+    use deno_core::v8;
+    use deno_core::JsRuntime;
+    use deno_core::RuntimeOptions;
+
+    use super::*;
+
+    /// Evaluates a JS expression yielding a `Uint8Array` and returns its bytes.
+    fn evaluate(runtime: &mut JsRuntime, expression: &str) -> Vec<u8> {
+        let script = format!(
+            "(() => {{\n\
+             const bytes = {expression};\n\
+             if (!(bytes instanceof Uint8Array)) throw new TypeError(\"not a Uint8Array\");\n\
+             return Array.from(bytes);\n\
+             }})()"
+        );
+        let result = runtime.execute_script("[js_bytes]", script.into()).unwrap();
+        let scope = &mut runtime.handle_scope();
+        let result = v8::Local::new(scope, result);
+        serde_v8::from_v8(scope, result).unwrap()
+    }
+
+    #[test]
+    fn embeds_bytes() {
+        let mut runtime = JsRuntime::new(RuntimeOptions::default());
+        let all: Vec<u8> = (0..=255).collect();
+        for len in 0..=all.len() {
+            let bytes = evaluate(&mut runtime, &js_bytes(&all[..len]));
+            assert_eq!(bytes, &all[..len], "{len}");
+        }
+    }
+
+    #[test]
+    fn wraps_raw_imports() {
+        let specifier = ModuleSpecifier::parse("file:///a.txt").unwrap();
+        assert_eq!(extension(&specifier), Some("txt"));
+        assert_eq!(
+            wrap(b"a \"b\"\n".to_vec(), RawImportType::Text, &specifier).unwrap(),
+            "export default \"a \\\"b\\\"\\n\";\n"
+        );
+        let error = wrap(vec![0xff], RawImportType::Text, &specifier).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<LoaderError>(),
+            Some(LoaderError::InvalidContent { .. })
+        ));
+        let code = wrap(vec![1, 2, 3], RawImportType::Bytes, &specifier).unwrap();
+        let expression = code.strip_prefix("export default ").unwrap();
+        let mut runtime = JsRuntime::new(RuntimeOptions::default());
+        let bytes = evaluate(&mut runtime, expression.strip_suffix(";\n").unwrap());
+        assert_eq!(bytes, [1, 2, 3]);
+    }
+}
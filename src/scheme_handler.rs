@@ -0,0 +1,218 @@
+use std::io::ErrorKind;
+
+use deno_ast::MediaType;
+use deno_core::anyhow;
+use deno_core::error::generic_error;
+use deno_core::futures::future::LocalBoxFuture;
+use deno_core::futures::FutureExt;
+use deno_core::ModuleSpecifier;
+
+use crate::remote_media_type;
+use crate::LoaderError;
+use crate::TypescriptModuleLoader;
+
+/// Fetches the modules of a URL scheme, e.g. `s3:` or `db:`. Register one
+/// with [`TypescriptModuleLoader::with_scheme_handler`].
+///
+/// The fetched module then goes through the same pipeline as built-in ones:
+/// it is transpiled, cached and wrapped according to its media type.
+pub trait SchemeHandler: Send + Sync {
+    fn fetch<'a>(
+        &'a self,
+        specifier: &'a ModuleSpecifier,
+    ) -> LocalBoxFuture<'a, Result<FetchedModule, anyhow::Error>>;
+}
+
+/// A module returned by a [`SchemeHandler`].
+pub struct FetchedModule {
+    /// Where the module was found, which differs from the requested
+    /// specifier when the handler followed a redirect.
+    pub specifier: ModuleSpecifier,
+    pub bytes: Vec<u8>,
+    pub media_type: MediaType,
+}
+
+impl FetchedModule {
+    /// Takes the media type from a content type, falling back to the
+    /// extension of the specifier when the content type is not specific.
+    pub fn with_content_type(
+        specifier: ModuleSpecifier,
+        bytes: Vec<u8>,
+        content_type: &str,
+    ) -> Self {
+        let media_type = remote_media_type(&specifier, Some(content_type), &[]);
+        Self {
+            specifier,
+            bytes,
+            media_type,
+        }
+    }
+}
+
+/// Built-in handler of `file:` specifiers.
+pub(crate) struct FileHandler<'a> {
+    pub loader: &'a TypescriptModuleLoader,
+}
+
+impl SchemeHandler for FileHandler<'_> {
+    fn fetch<'a>(
+        &'a self,
+        specifier: &'a ModuleSpecifier,
+    ) -> LocalBoxFuture<'a, Result<FetchedModule, anyhow::Error>> {
+        async move {
+            let path = specifier
+                .to_file_path()
+                .map_err(|_| generic_error(format!("Invalid file specifier: {specifier}")))?;
+            // Files of downloaded npm packages, which import each other
+            // through `file:` specifiers, are exempt from read permissions.
+            let path = if path.starts_with(self.loader.npm_registry.root()) {
+                path
+            } else {
+                self.loader
+                    .permissions
+                    .check_read(&path)
+                    .map_err(|e| not_found(e, specifier))?
+            };
+            let bytes = tokio::fs::read(&path)
+                .await
+                .map_err(|e| not_found(e.into(), specifier))?;
+            Ok(FetchedModule {
+                specifier: specifier.clone(),
+                bytes,
+                media_type: MediaType::from_path(&path),
+            })
+        }
+        .boxed_local()
+    }
+}
+
+/// Built-in handler of `http:` and `https:` specifiers, which caches modules
+/// and checks them against the lockfile.
+pub(crate) struct HttpHandler<'a> {
+    pub loader: &'a TypescriptModuleLoader,
+}
+
+impl SchemeHandler for HttpHandler<'_> {
+    fn fetch<'a>(
+        &'a self,
+        specifier: &'a ModuleSpecifier,
+    ) -> LocalBoxFuture<'a, Result<FetchedModule, anyhow::Error>> {
+        async move {
+            let remote = self.loader.fetch_remote(specifier).await?;
+            if let Some(lockfile) = &self.loader.lockfile {
+                lockfile
+                    .lock()
+                    .unwrap()
+                    .check_or_insert(remote.specifier.as_str(), &remote.body)?;
+            }
+            let media_type = remote_media_type(
+                &remote.specifier,
+                remote.headers.get("content-type").map(String::as_str),
+                &self.loader.media_type_overrides,
+            );
+            Ok(FetchedModule {
+                specifier: remote.specifier,
+                bytes: remote.body,
+                media_type,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+/// Built-in handler of `npm:` specifiers, serving files of packages
+/// downloaded to the npm cache.
+pub(crate) struct NpmHandler<'a> {
+    pub loader: &'a TypescriptModuleLoader,
+}
+
+impl SchemeHandler for NpmHandler<'_> {
+    fn fetch<'a>(
+        &'a self,
+        specifier: &'a ModuleSpecifier,
+    ) -> LocalBoxFuture<'a, Result<FetchedModule, anyhow::Error>> {
+        async move {
+            let found_specifier = self.loader.resolve_npm_specifier(specifier).await?;
+            let path = found_specifier
+                .to_file_path()
+                .map_err(|_| generic_error(format!("Invalid file specifier: {found_specifier}")))?;
+            let bytes = tokio::fs::read(&path)
+                .await
+                .map_err(|e| not_found(e.into(), &found_specifier))?;
+            Ok(FetchedModule {
+                specifier: found_specifier,
+                bytes,
+                media_type: MediaType::from_path(&path),
+            })
+        }
+        .boxed_local()
+    }
+}
+
+/// Built-in handler of `jsr:` specifiers, which are fetched from the
+/// `https:` URL they map to.
+pub(crate) struct JsrHandler<'a> {
+    pub loader: &'a TypescriptModuleLoader,
+}
+
+impl SchemeHandler for JsrHandler<'_> {
+    fn fetch<'a>(
+        &'a self,
+        specifier: &'a ModuleSpecifier,
+    ) -> LocalBoxFuture<'a, Result<FetchedModule, anyhow::Error>> {
+        async move {
+            let https_specifier = self.loader.resolve_jsr_specifier(specifier).await?;
+            HttpHandler {
+                loader: self.loader,
+            }
+            .fetch(&https_specifier)
+            .await
+        }
+        .boxed_local()
+    }
+}
+
+/// Built-in handler of `data:` URLs, decoding their base64 or
+/// percent-encoded payload and taking the media type from their MIME type.
+pub(crate) struct DataUrlHandler;
+
+impl SchemeHandler for DataUrlHandler {
+    fn fetch<'a>(
+        &'a self,
+        specifier: &'a ModuleSpecifier,
+    ) -> LocalBoxFuture<'a, Result<FetchedModule, anyhow::Error>> {
+        async move {
+            let data_url = data_url::DataUrl::process(specifier.as_str())
+                .map_err(|e| invalid_data_url(format!("{e:?}"), specifier))?;
+            let mime_type = data_url.mime_type();
+            let content_type = format!("{}/{}", mime_type.type_, mime_type.subtype);
+            let (bytes, _) = data_url
+                .decode_to_vec()
+                .map_err(|e| invalid_data_url(format!("{e:?}"), specifier))?;
+            Ok(FetchedModule {
+                specifier: specifier.clone(),
+                bytes,
+                media_type: MediaType::from_content_type(specifier, &content_type),
+            })
+        }
+        .boxed_local()
+    }
+}
+
+fn invalid_data_url(message: String, specifier: &ModuleSpecifier) -> LoaderError {
+    LoaderError::InvalidContent {
+        specifier: specifier.clone(),
+        message: format!("invalid data URL: {message}"),
+    }
+}
+
+/// Turns an error about a missing file into [`LoaderError::NotFound`].
+fn not_found(error: anyhow::Error, specifier: &ModuleSpecifier) -> anyhow::Error {
+    match error.downcast_ref::<std::io::Error>() {
+        Some(e) if e.kind() == ErrorKind::NotFound => LoaderError::NotFound {
+            specifier: specifier.clone(),
+        }
+        .into(),
+        _ => error,
+    }
+}
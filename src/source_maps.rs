@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use base64::Engine;
+use deno_core::SourceMapGetter;
+
+const SOURCE_MAP_PREFIX: &str = "//# sourceMappingURL=data:application/json;base64,";
+
+/// Source maps of the modules transpiled by a [`crate::TypescriptModuleLoader`],
+/// so that `JsRuntime` stack traces point at the original TypeScript.
+///
+/// Pass it as `RuntimeOptions::source_map_getter`; it shares its contents with
+/// the loader it was taken from, so modules loaded later are covered too.
+#[derive(Clone, Default)]
+pub struct EmittedSourceMaps {
+    sources: Arc<Mutex<HashMap<String, EmittedSource>>>,
+}
+
+struct EmittedSource {
+    source_map: Vec<u8>,
+    original: String,
+}
+
+impl EmittedSourceMaps {
+    /// Records the inline source map of `emitted`, if it has one.
+    pub(crate) fn insert(&self, specifier: &str, emitted: &str, original: &str) {
+        let Some(source_map) = inline_source_map(emitted) else {
+            return;
+        };
+        self.sources.lock().unwrap().insert(
+            specifier.to_string(),
+            EmittedSource {
+                source_map,
+                original: original.to_string(),
+            },
+        );
+    }
+}
+
+fn inline_source_map(code: &str) -> Option<Vec<u8>> {
+    let line = code.lines().rev().find(|line| !line.trim().is_empty())?;
+    let encoded = line.strip_prefix(SOURCE_MAP_PREFIX)?;
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()
+}
+
+impl SourceMapGetter for EmittedSourceMaps {
+    fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        let sources = self.sources.lock().unwrap();
+        Some(sources.get(file_name)?.source_map.clone())
+    }
+
+    fn get_source_line(&self, file_name: &str, line_number: usize) -> Option<String> {
+        let sources = self.sources.lock().unwrap();
+        let line = sources.get(file_name)?.original.lines().nth(line_number)?;
+        Some(line.to_string())
+    }
+}
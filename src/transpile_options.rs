@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use anyhow::bail;
+use deno_ast::EmitOptions;
+use deno_core::anyhow;
+use deno_core::serde_json::Map;
+use deno_core::serde_json::Value;
+
+/// How JSX is transformed, matching the `jsx` compiler option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsxRuntime {
+    /// `"react"`: calls to `jsxFactory` and `jsxFragmentFactory`.
+    #[default]
+    Classic,
+    /// `"react-jsx"`: imports from `<jsxImportSource>/jsx-runtime`.
+    Automatic,
+    /// `"react-jsxdev"`: imports from `<jsxImportSource>/jsx-dev-runtime`.
+    AutomaticDevelopment,
+}
+
+/// Options for transpiling TypeScript and JSX modules.
+///
+/// Decorators are always transpiled with TypeScript's legacy
+/// (`experimentalDecorators`) semantics.
+#[derive(Clone, Debug)]
+pub struct TranspileOptions {
+    pub jsx: JsxRuntime,
+    pub jsx_factory: String,
+    pub jsx_fragment_factory: String,
+    pub jsx_import_source: Option<String>,
+    pub emit_decorator_metadata: bool,
+}
+
+impl Default for TranspileOptions {
+    fn default() -> Self {
+        Self {
+            jsx: JsxRuntime::Classic,
+            jsx_factory: "React.createElement".to_string(),
+            jsx_fragment_factory: "React.Fragment".to_string(),
+            jsx_import_source: None,
+            emit_decorator_metadata: false,
+        }
+    }
+}
+
+impl TranspileOptions {
+    /// Builds the options from a `compilerOptions` object, ignoring the
+    /// compiler options that do not affect transpiling.
+    pub fn from_compiler_options(compiler_options: &Value) -> Result<Self, anyhow::Error> {
+        let mut options = Self::default();
+        let Some(compiler_options) = compiler_options.as_object() else {
+            bail!("compilerOptions must be an object");
+        };
+        if let Some(jsx) = compiler_options.get("jsx") {
+            options.jsx = match jsx.as_str() {
+                Some("react") => JsxRuntime::Classic,
+                Some("react-jsx") => JsxRuntime::Automatic,
+                Some("react-jsxdev") => JsxRuntime::AutomaticDevelopment,
+                // V8 can't run JSX, so it always has to be transformed.
+                Some("preserve") => {
+                    bail!("Unsupported jsx compiler option \"preserve\": JSX must be transformed")
+                }
+                _ => bail!("Unsupported jsx compiler option: {jsx}"),
+            };
+        }
+        if let Some(factory) = string_option(compiler_options, "jsxFactory")? {
+            options.jsx_factory = factory;
+        }
+        if let Some(factory) = string_option(compiler_options, "jsxFragmentFactory")? {
+            options.jsx_fragment_factory = factory;
+        }
+        options.jsx_import_source = string_option(compiler_options, "jsxImportSource")?;
+        if let Some(emit) = compiler_options.get("emitDecoratorMetadata") {
+            let Some(emit) = emit.as_bool() else {
+                bail!("emitDecoratorMetadata compiler option must be a boolean");
+            };
+            options.emit_decorator_metadata = emit;
+        }
+        Ok(options)
+    }
+
+    /// Reads the `compilerOptions` of a `tsconfig.json` or `deno.json` file,
+    /// which may contain comments.
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let config =
+            jsonc_parser::parse_to_serde_value(&text, &Default::default())?.unwrap_or(Value::Null);
+        match config.get("compilerOptions") {
+            Some(compiler_options) => Self::from_compiler_options(compiler_options),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub(crate) fn emit_options(&self) -> EmitOptions {
+        EmitOptions {
+            emit_metadata: self.emit_decorator_metadata,
+            inline_source_map: true,
+            jsx_automatic: matches!(
+                self.jsx,
+                JsxRuntime::Automatic | JsxRuntime::AutomaticDevelopment
+            ),
+            jsx_development: self.jsx == JsxRuntime::AutomaticDevelopment,
+            jsx_factory: self.jsx_factory.clone(),
+            jsx_fragment_factory: self.jsx_fragment_factory.clone(),
+            jsx_import_source: self.jsx_import_source.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn string_option(
+    compiler_options: &Map<String, Value>,
+    name: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    match compiler_options.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => bail!("{name} compiler option must be a string"),
+    }
+}
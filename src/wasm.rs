@@ -0,0 +1,287 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use anyhow::bail;
+use deno_core::anyhow;
+
+use crate::cjs::js_string;
+use crate::raw_imports::js_bytes;
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: &[u8] = &[1, 0, 0, 0];
+
+const IMPORT_SECTION: u8 = 2;
+const EXPORT_SECTION: u8 = 7;
+
+/// Wraps a WebAssembly module in an ES module that instantiates it and
+/// exports the instance's exports under their own names.
+///
+/// Each module the wasm imports from is imported statically, so it loads
+/// through the same loader and resolves relative to the `.wasm` file. The
+/// default export is the wasm export named `default` if there is one, and
+/// otherwise the instance's whole `exports` object.
+pub fn wrap(bytes: &[u8]) -> Result<String, anyhow::Error> {
+    let (imports, exports) = parse_imports_and_exports(bytes)?;
+
+    let mut code = String::new();
+    let mut import_object = String::new();
+    for (i, module) in imports.iter().enumerate() {
+        writeln!(
+            code,
+            "import * as __wasm_dep{i} from {};",
+            js_string(module)
+        )
+        .unwrap();
+        write!(import_object, "{}: __wasm_dep{i},", js_string(module)).unwrap();
+    }
+    writeln!(
+        code,
+        "const __wasm_bytes = {};\n\
+         const __wasm_instance = new WebAssembly.Instance(new WebAssembly.Module(__wasm_bytes), {{{import_object}}});\n\
+         const __wasm_exports = __wasm_instance.exports;",
+        js_bytes(bytes)
+    )
+    .unwrap();
+    if !exports.contains("default") {
+        writeln!(code, "export default __wasm_exports;").unwrap();
+    }
+    for (i, name) in exports.iter().enumerate() {
+        writeln!(
+            code,
+            "const __wasm_export{i} = __wasm_exports[{0}];\nexport {{ __wasm_export{i} as {0} }};",
+            js_string(name)
+        )
+        .unwrap();
+    }
+    Ok(code)
+}
+
+/// Reads the names of the modules imported by a wasm module, and the names
+/// it exports, from its import and export sections.
+fn parse_imports_and_exports(
+    bytes: &[u8],
+) -> Result<(BTreeSet<String>, BTreeSet<String>), anyhow::Error> {
+    if bytes.len() < 8 || &bytes[..4] != MAGIC {
+        bail!("Not a WebAssembly module");
+    }
+    if &bytes[4..8] != VERSION {
+        bail!("Unsupported WebAssembly version");
+    }
+    let mut imports = BTreeSet::new();
+    let mut exports = BTreeSet::new();
+    let mut reader = Reader { bytes, position: 8 };
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let size = reader.u32()? as usize;
+        let mut section = Reader {
+            bytes: reader.take(size)?,
+            position: 0,
+        };
+        match id {
+            IMPORT_SECTION => {
+                for _ in 0..section.u32()? {
+                    imports.insert(section.name()?);
+                    section.name()?;
+                    section.import_desc()?;
+                }
+            }
+            EXPORT_SECTION => {
+                for _ in 0..section.u32()? {
+                    exports.insert(section.name()?);
+                    section.byte()?;
+                    section.u32()?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((imports, exports))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], anyhow::Error> {
+        let Some(bytes) = self.bytes.get(self.position..self.position + len) else {
+            bail!("Unexpected end of WebAssembly module");
+        };
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, anyhow::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// An unsigned LEB128 integer.
+    fn u32(&mut self) -> Result<u32, anyhow::Error> {
+        let mut result = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            result |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        bail!("Invalid integer in WebAssembly module");
+    }
+
+    fn name(&mut self) -> Result<String, anyhow::Error> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    fn limits(&mut self) -> Result<(), anyhow::Error> {
+        let flags = self.byte()?;
+        self.u32()?;
+        if flags & 1 != 0 {
+            self.u32()?;
+        }
+        Ok(())
+    }
+
+    /// Skips the description of what an import is: a function, table,
+    /// memory, global or tag.
+    fn import_desc(&mut self) -> Result<(), anyhow::Error> {
+        match self.byte()? {
+            0 => {
+                self.u32()?;
+            }
+            1 => {
+                self.byte()?;
+                self.limits()?;
+            }
+            2 => self.limits()?,
+            3 => {
+                self.byte()?;
+                self.byte()?;
+            }
+            4 => {
+                self.byte()?;
+                self.u32()?;
+            }
+            kind => bail!("Unknown import kind {kind} in WebAssembly module"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leb(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        [leb(name.len()), name.as_bytes().to_vec()].concat()
+    }
+
+    fn section(id: u8, content: Vec<u8>) -> Vec<u8> {
+        [vec![id], leb(content.len()), content].concat()
+    }
+
+    fn module(sections: &[Vec<u8>]) -> Vec<u8> {
+        [MAGIC, VERSION]
+            .concat()
+            .into_iter()
+            .chain(sections.concat())
+            .collect()
+    }
+
+    fn example() -> Vec<u8> {
+        let imports = [
+            vec![3],
+            // A function of type 0.
+            name("env"),
+            name("f"),
+            vec![0, 0],
+            // A memory with a minimum and a maximum.
+            name("./dep.js"),
+            name("mem"),
+            vec![2, 1, 1, 2],
+            // An immutable i32 global.
+            name("env"),
+            name("g"),
+            vec![3, 0x7f, 0],
+        ]
+        .concat();
+        let exports = [
+            vec![2],
+            name("add"),
+            vec![0, 0],
+            name("default"),
+            vec![2, 0],
+        ]
+        .concat();
+        module(&[
+            // A custom section larger than a single byte LEB128 size.
+            section(0, [name("custom"), vec![0; 200]].concat()),
+            section(IMPORT_SECTION, imports),
+            section(EXPORT_SECTION, exports),
+        ])
+    }
+
+    #[test]
+    fn parses_imports_and_exports() {
+        let (imports, exports) = parse_imports_and_exports(&example()).unwrap();
+        assert_eq!(
+            imports,
+            BTreeSet::from(["./dep.js".to_string(), "env".to_string()])
+        );
+        assert_eq!(
+            exports,
+            BTreeSet::from(["add".to_string(), "default".to_string()])
+        );
+
+        let (imports, exports) = parse_imports_and_exports(&module(&[])).unwrap();
+        assert!(imports.is_empty() && exports.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_modules() {
+        assert!(parse_imports_and_exports(b"\0asm").is_err());
+        assert!(parse_imports_and_exports(b"\0wasm\x01\0\0\0").is_err());
+        assert!(parse_imports_and_exports(b"\0asm\x02\0\0\0").is_err());
+        let example = example();
+        assert!(parse_imports_and_exports(&example[..example.len() - 1]).is_err());
+        let unknown_kind = [vec![1], name("env"), name("x"), vec![9]].concat();
+        let bytes = module(&[section(IMPORT_SECTION, unknown_kind)]);
+        assert!(parse_imports_and_exports(&bytes).is_err());
+        let endless_integer = module(&[vec![0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]]);
+        assert!(parse_imports_and_exports(&endless_integer).is_err());
+    }
+
+    #[test]
+    fn wraps_modules() {
+        let code = wrap(&example()).unwrap();
+        assert!(code.starts_with(
+            "import * as __wasm_dep0 from \"./dep.js\";\n\
+             import * as __wasm_dep1 from \"env\";\n"
+        ));
+        assert!(code.contains("{\"./dep.js\": __wasm_dep0,\"env\": __wasm_dep1,}"));
+        assert!(code.contains("export { __wasm_export0 as \"add\" };"));
+        assert!(code.contains("export { __wasm_export1 as \"default\" };"));
+        assert!(!code.contains("export default"));
+
+        let code = wrap(&module(&[])).unwrap();
+        assert!(code.contains("export default __wasm_exports;"));
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use base64::Engine;
+use basic_deno_ts_module_loader::LoaderError;
+use basic_deno_ts_module_loader::TypescriptModuleLoader;
+use deno_core::serde_json::json;
+use deno_core::ModuleLoader;
+use deno_core::ModuleSpecifier;
+use deno_core::ResolutionKind;
+use sha2::Digest;
+use sha2::Sha512;
+
+const FILES: &[(&str, &str)] = &[
+    (
+        "package.json",
+        r#"{ "name": "greet", "version": "1.0.0", "type": "module", "exports": "./index.js" }"#,
+    ),
+    (
+        "index.js",
+        "import { suffix } from \"./util.js\";\nexport const greet = (name) => `Hello, ${name}${suffix}`;\n",
+    ),
+    ("util.js", "export const suffix = \"!\";\n"),
+];
+
+/// A registry serving a single version of the `greet` package.
+struct Registry {
+    url: ModuleSpecifier,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Registry {
+    fn start(integrity: Option<&str>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url =
+            ModuleSpecifier::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let tarball = tarball();
+        let integrity = integrity.map(str::to_string).unwrap_or_else(|| {
+            let digest = Sha512::digest(&tarball);
+            format!(
+                "sha512-{}",
+                base64::engine::general_purpose::STANDARD.encode(digest)
+            )
+        });
+        let packument = json!({
+            "name": "greet",
+            "dist-tags": { "latest": "1.0.0" },
+            "versions": {
+                "1.0.0": {
+                    "dist": {
+                        "tarball": url.join("greet/-/greet-1.0.0.tgz").unwrap().as_str(),
+                        "integrity": integrity,
+                    },
+                },
+            },
+        })
+        .to_string();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let path = request_line.split(' ').nth(1).unwrap_or_default();
+                let (status, body) = match path {
+                    "/greet" => ("200 OK", packument.as_bytes()),
+                    "/greet/-/greet-1.0.0.tgz" => ("200 OK", tarball.as_slice()),
+                    _ => ("404 Not Found", &b""[..]),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        Self { url, requests }
+    }
+
+    fn loader(&self, cache_dir: &Path) -> TypescriptModuleLoader {
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        TypescriptModuleLoader::new(http, cache_dir).with_npm_registry(self.url.clone())
+    }
+}
+
+fn tarball() -> Vec<u8> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (name, content) in FILES {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, format!("package/{name}"), content.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "basic_deno_ts_module_loader-{name}-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[tokio::test]
+async fn loads_npm_packages_from_the_registry() {
+    let registry = Registry::start(None);
+    let cache_dir = cache_dir("npm");
+    let loader = registry.loader(&cache_dir);
+
+    let specifier = ModuleSpecifier::parse("npm:greet@^1.0.0").unwrap();
+    let source = loader.load(&specifier, None, false).await.unwrap();
+    assert!(source.code.as_str().contains("export const greet"));
+    assert_eq!(registry.requests.load(Ordering::SeqCst), 2);
+
+    // The package is unpacked under the cache directory, where its modules
+    // import each other through `file:` specifiers.
+    let host = format!("127.0.0.1_PORT{}", registry.url.port().unwrap());
+    let package_dir = cache_dir.join("npm").join(host).join("greet/1.0.0");
+    assert!(package_dir.join(".initialized").is_file());
+    let index = ModuleSpecifier::from_file_path(package_dir.join("index.js")).unwrap();
+    let util = loader
+        .resolve("./util.js", index.as_str(), ResolutionKind::Import)
+        .unwrap();
+    assert_eq!(util.to_file_path().unwrap(), package_dir.join("util.js"));
+    let source = loader.load(&util, Some(&index), false).await.unwrap();
+    assert!(source.code.as_str().contains("suffix"));
+
+    // Once downloaded, the package is served from the cache.
+    let loader = registry.loader(&cache_dir).with_cached_only(true);
+    loader.load(&specifier, None, false).await.unwrap();
+    assert_eq!(registry.requests.load(Ordering::SeqCst), 2);
+
+    // Versions and subpaths the package does not have.
+    for missing in ["npm:greet@^2.0.0", "npm:greet/util.js"] {
+        let missing = ModuleSpecifier::parse(missing).unwrap();
+        let error = loader.load(&missing, None, false).await.unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref::<LoaderError>(),
+                Some(LoaderError::NotFound { .. })
+            ),
+            "{missing}"
+        );
+    }
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_tarballs_with_a_different_checksum() {
+    let registry = Registry::start(Some("sha512-AAAA"));
+    let cache_dir = cache_dir("npm-integrity");
+    let specifier = ModuleSpecifier::parse("npm:greet").unwrap();
+    let error = registry
+        .loader(&cache_dir)
+        .load(&specifier, None, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<LoaderError>(),
+        Some(LoaderError::IntegrityMismatch { .. })
+    ));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}